@@ -1,93 +1,230 @@
 #[allow(unused_imports)]
-use ff::Field;
-use halo2_proofs::dev::MockProver;
+use ff::{Field, FromUniformBytes, PrimeField, WithSmallOrderMulGroup};
+use halo2_proofs::arithmetic::CurveAffine;
+use halo2_proofs::dev::{CircuitCost, MockProver};
 use halo2_proofs::{
     circuit::{Value},
     plonk::{
-        create_proof, keygen_pk, keygen_vk, verify_proof
+        create_proof, keygen_pk, keygen_vk, verify_proof, Circuit, ProvingKey
     },
     poly::{
-        commitment::ParamsProver,
+        commitment::{ParamsProver, Prover, Verifier},
         ipa::{
             commitment::{IPACommitmentScheme, ParamsIPA},
             multiopen::ProverIPA,
             strategy::SingleStrategy,
         },
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::{ProverGWC, ProverSHPLONK, VerifierGWC, VerifierSHPLONK},
+            strategy::SingleStrategy as KZGSingleStrategy,
+        },
         VerificationStrategy,
     },
     transcript::{
         Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
     },
 };
-use halo2curves::pasta::{vesta, EqAffine, Fp};
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine, G1};
+use halo2curves::pasta::{pallas, vesta, Fp};
 
 use criterion::{criterion_group, criterion_main, Criterion};
 use rand::rngs::OsRng;
 
-use halo2_mt::circuits::merkle::{compute_merkle_root, MerkleTreeCircuit};
+use halo2_gadgets::poseidon::primitives::Spec;
+use halo2_mt::circuits::merkle::{
+    compute_merkle_root_with, MerkleTreeCircuit, PrivateMerkleTreeCircuit,
+};
+use halo2_mt::trees::merkle::MerkleTree;
+use halo2_mt::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+use halo2_mt::utils::p128pow5t5::P128Pow5T5;
+use std::marker::PhantomData;
 
-/// Benchmark merkle proof creation and verification using a merkle tree of `depth`.
+/// Benchmark merkle proof creation and verification using a merkle tree of `depth`, both with a public and with a
+/// private leaf. The tree is over the scalar field of the curve `E` that IPA commits with: Vesta proves trees over
+/// `Fp`, and Pallas trees over `Fq`.
 ///
 /// `degree` is the max polynomial degree our polynomial commitment scheme (IPA) can support. The degree should be
 /// larger than the number of rows of our circuit.
-fn bench_merkle(depth: usize, degree: u32, c: &mut Criterion) {
-    // Initialize the polynomial commitment parameters
-    let params: ParamsIPA<vesta::Affine> = ParamsIPA::new(degree);
+fn bench_merkle<E>(name: &str, depth: usize, degree: u32, c: &mut Criterion)
+where
+    E: CurveAffine,
+    E::Scalar: WithSmallOrderMulGroup<3> + FromUniformBytes<64>,
+    OrchardNullifier: Spec<E::Scalar, 3, 2>,
+{
+    let mut rng = OsRng;
 
-    // Preprocess the SNARK and generate the proving/verifying keys
-    // We will need an empty circuit to preprocess it
-    let empty_circuit = MerkleTreeCircuit {
+    // Build a tree holding a bunch of random leaves and pick one of them to prove membership of
+    let leaves = (0..1024).map(|_| E::Scalar::random(&mut rng)).collect::<Vec<_>>();
+    let tree = MerkleTree::new(depth, leaves);
+    let path = tree.path(42);
+    let leaf_f = path.leaf;
+    let digest = tree.root();
+
+    // We will need an empty circuit to preprocess the SNARK
+    let empty_circuit: MerkleTreeCircuit<E::Scalar> = MerkleTreeCircuit {
         leaf: Value::unknown(),
         elements: vec![Value::unknown(); depth],
         indices: vec![Value::unknown(); depth],
+        _spec: PhantomData,
     };
-    let vk = keygen_vk(&params, &empty_circuit).expect("keygen_vk should not fail");
-    let pk = keygen_pk(&params, vk, &empty_circuit).expect("keygen_pk should not fail");
+    // Our instance is the leaf and the merkle root
+    bench_circuit::<E, _>(
+        &format!("{}-{}", name, depth),
+        degree,
+        &empty_circuit,
+        MerkleTreeCircuit::from(path.clone()),
+        vec![leaf_f, digest],
+        c,
+    );
 
-    // Do a bunch of additional legwork
-    let prover_name = format!("MT-{}-prover", depth);
-    let verifier_name = format!("MT-{}-verifier", depth);
-    let mut rng = OsRng;
+    let empty_circuit = PrivateMerkleTreeCircuit {
+        leaf: Value::unknown(),
+        elements: vec![Value::unknown(); depth],
+        indices: vec![Value::unknown(); depth],
+    };
+    // Our instance is just the merkle root
+    bench_circuit::<E, _>(
+        &format!("{}-private-{}", name, depth),
+        degree,
+        &empty_circuit,
+        PrivateMerkleTreeCircuit::from(path),
+        vec![digest],
+        c,
+    );
+}
 
-    // Generate a random leaf, a bunch of siblings and a route to the root
-    let leaf_f = Fp::random(&mut rng);
-    let elements_f = (0..depth).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
-    let indices = vec![0u64; depth];
-    // Compute the merkle root
-    let digest = compute_merkle_root(&leaf_f, &elements_f, &indices);
+/// A membership proof for a random path of `depth` in a tree with `ARITY` children per node, hashed with the Poseidon
+/// `S` of `WIDTH` and `RATE` over `F`. Returns the empty circuit to preprocess the SNARK with, the circuit and its
+/// public inputs, the leaf and the merkle root.
+fn random_merkle_circuit<
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
+    const WIDTH: usize,
+    const RATE: usize,
+    const ARITY: usize,
+>(
+    depth: usize,
+) -> (
+    MerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>,
+    MerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>,
+    Vec<F>,
+) {
+    let mut rng = OsRng;
 
-    // Convert everything to circuit values
-    let leaf_fp = Value::known(leaf_f);
-    let elements_fp: Vec<Value<Fp>> = elements_f.iter().map(|x| Value::known(x.to_owned())).collect();
-    let indices_fp: Vec<Value<Fp>> = indices.iter().map(|x| Value::known(Fp::from(x.to_owned()))).collect();
+    // The siblings of a random path are as good as the ones of a full tree for proving
+    let leaf = F::random(&mut rng);
+    let elements = (0..depth * (ARITY - 1))
+        .map(|_| F::random(&mut rng))
+        .collect::<Vec<_>>();
+    let indices = (0..depth).map(|i| (i % ARITY) as u64).collect::<Vec<_>>();
+    let digest = compute_merkle_root_with::<F, S, WIDTH, RATE, ARITY>(&leaf, &elements, &indices);
 
-    // Populate an actual circuit with the trace
+    let empty_circuit = MerkleTreeCircuit {
+        leaf: Value::unknown(),
+        elements: vec![Value::unknown(); elements.len()],
+        indices: vec![Value::unknown(); depth],
+        _spec: PhantomData,
+    };
     let circuit = MerkleTreeCircuit {
-        leaf: leaf_fp,
-        elements: elements_fp.clone(),
-        indices: indices_fp.clone(),
+        leaf: Value::known(leaf),
+        elements: elements.into_iter().map(Value::known).collect(),
+        indices: indices.iter().map(|x| Value::known(F::from(*x))).collect(),
+        _spec: PhantomData,
     };
+    (empty_circuit, circuit, vec![leaf, digest])
+}
 
+/// Benchmark merkle proof creation and verification using a merkle tree of `depth` with `ARITY` children per node,
+/// hashed with the Poseidon `S` of `WIDTH` and `RATE`. A tree of arity 4 and depth `n` holds as many leaves as the
+/// binary tree of depth `2n` benchmarked by `bench_merkle`.
+fn bench_merkle_with<
+    S: Spec<Fp, WIDTH, RATE> + Clone,
+    const WIDTH: usize,
+    const RATE: usize,
+    const ARITY: usize,
+>(
+    depth: usize,
+    degree: u32,
+    c: &mut Criterion,
+) {
+    let (empty_circuit, circuit, public_input) =
+        random_merkle_circuit::<Fp, S, WIDTH, RATE, ARITY>(depth);
     // Our instance is the leaf and the merkle root
-    let public_input = vec![leaf_f, digest];
+    bench_circuit::<vesta::Affine, _>(
+        &format!("MT{}-{}", ARITY, depth),
+        degree,
+        &empty_circuit,
+        circuit,
+        public_input,
+        c,
+    );
+}
+
+/// Same as `bench_merkle` with a public leaf, but over BN254 with KZG commitments instead of IPA over Pasta
+fn bench_merkle_kzg(depth: usize, degree: u32, c: &mut Criterion) {
+    let (empty_circuit, circuit, public_input) =
+        random_merkle_circuit::<Fr, OrchardNullifier, 3, 2, 2>(depth);
+    bench_circuit_kzg(
+        &format!("MT-KZG-{}", depth),
+        degree,
+        &empty_circuit,
+        circuit,
+        public_input,
+        c,
+    );
+}
+
+/// Benchmark proof creation and verification of `circuit` with `public_input` as its single instance column, with IPA
+/// commitments on the curve `E`. `empty_circuit` is used to generate the proving and verifying keys.
+fn bench_circuit<E, C>(
+    name: &str,
+    degree: u32,
+    empty_circuit: &C,
+    circuit: C,
+    public_input: Vec<E::Scalar>,
+    c: &mut Criterion,
+) where
+    E: CurveAffine,
+    E::Scalar: WithSmallOrderMulGroup<3> + FromUniformBytes<64>,
+    C: Circuit<E::Scalar> + Clone,
+{
+    // Initialize the polynomial commitment parameters
+    let params: ParamsIPA<E> = ParamsIPA::new(degree);
+
+    // Preprocess the SNARK and generate the proving/verifying keys
+    let vk = keygen_vk(&params, empty_circuit).expect("keygen_vk should not fail");
+    let pk = keygen_pk(&params, vk, empty_circuit).expect("keygen_pk should not fail");
+
+    // Do a bunch of additional legwork
+    let prover_name = format!("{}-prover", name);
+    let verifier_name = format!("{}-verifier", name);
+    let mut rng = OsRng;
+
     // Check proof creation using a MockProver: a sanity check to make sure that things make sense
-    // XXX why do we have to pass public_input twice?
     let prover = MockProver::run(
         degree,
         &circuit,
-        vec![public_input.clone(), public_input.clone()],
+        vec![public_input.clone()],
     ).unwrap();
     prover.assert_satisfied();
 
+    // The number of rows the circuit uses, along with the rest of its cost
+    println!(
+        "{} cost: {:?}",
+        name,
+        CircuitCost::<E::CurveExt, C>::measure(degree as _, &circuit)
+    );
+
     // Benchmark proof creation
     c.bench_function(&prover_name, |b| {
         b.iter(|| {
-            let mut transcript = Blake2bWrite::<_, EqAffine, Challenge255<_>>::init(vec![]);
+            let mut transcript = Blake2bWrite::<_, E, Challenge255<_>>::init(vec![]);
             create_proof::<IPACommitmentScheme<_>, ProverIPA<_>, _, _, _, _>(
                 &params,
                 &pk,
                 &[circuit.clone()],
-                &[&[&public_input, &public_input]],
+                &[&[&public_input]],
                 &mut rng,
                 &mut transcript,
             )
@@ -98,17 +235,17 @@ fn bench_merkle(depth: usize, degree: u32, c: &mut Criterion) {
     // Now let's move towards benchmarking verifier
 
     // Create the proof that will be verified
-    let mut transcript = Blake2bWrite::<_, EqAffine, Challenge255<_>>::init(vec![]);
+    let mut transcript = Blake2bWrite::<_, E, Challenge255<_>>::init(vec![]);
     create_proof::<IPACommitmentScheme<_>, ProverIPA<_>, _, _, _, _>(
         &params,
         &pk,
         &[circuit],
-        &[&[&public_input, &public_input]],
+        &[&[&public_input]],
         &mut rng,
         &mut transcript,
     ).expect("proof generation should not fail");
     let proof = transcript.finalize();
-    println!("proof length: {}", proof.len());
+    println!("{} proof length: {}", name, proof.len());
 
     // Benchmark the verifier
     c.bench_function(&verifier_name, |b| {
@@ -119,7 +256,121 @@ fn bench_merkle(depth: usize, degree: u32, c: &mut Criterion) {
                 &params,
                 pk.get_vk(),
                 strategy,
-                &[&[&public_input, &public_input]],
+                &[&[&public_input]],
+                &mut transcript
+            )
+            .is_ok());
+        });
+    });
+}
+
+/// Same as `bench_circuit`, but over BN254 with KZG commitments. Proofs are created and verified with both the SHPLONK
+/// and the GWC multiopen arguments.
+fn bench_circuit_kzg<C: Circuit<Fr> + Clone>(
+    name: &str,
+    degree: u32,
+    empty_circuit: &C,
+    circuit: C,
+    public_input: Vec<Fr>,
+    c: &mut Criterion,
+) {
+    // Initialize the polynomial commitment parameters. This is an unsafe trusted setup, which is fine for benchmarks.
+    let params = ParamsKZG::<Bn256>::setup(degree, OsRng);
+
+    // Preprocess the SNARK and generate the proving/verifying keys
+    let vk = keygen_vk(&params, empty_circuit).expect("keygen_vk should not fail");
+    let pk = keygen_pk(&params, vk, empty_circuit).expect("keygen_pk should not fail");
+
+    // Check proof creation using a MockProver: a sanity check to make sure that things make sense
+    let prover = MockProver::run(
+        degree,
+        &circuit,
+        vec![public_input.clone()],
+    ).unwrap();
+    prover.assert_satisfied();
+
+    // The number of rows the circuit uses, along with the rest of its cost
+    println!(
+        "{} cost: {:?}",
+        name,
+        CircuitCost::<G1, C>::measure(degree as _, &circuit)
+    );
+
+    bench_kzg::<_, ProverSHPLONK<_>, VerifierSHPLONK<_>>(
+        &format!("{}-shplonk", name),
+        &params,
+        &pk,
+        circuit.clone(),
+        &public_input,
+        c,
+    );
+    bench_kzg::<_, ProverGWC<_>, VerifierGWC<_>>(
+        &format!("{}-gwc", name),
+        &params,
+        &pk,
+        circuit,
+        &public_input,
+        c,
+    );
+}
+
+/// Benchmark KZG proof creation with the prover `P` and verification with the matching verifier `V`
+fn bench_kzg<'params, C, P, V>(
+    name: &str,
+    params: &'params ParamsKZG<Bn256>,
+    pk: &ProvingKey<G1Affine>,
+    circuit: C,
+    public_input: &[Fr],
+    c: &mut Criterion,
+) where
+    C: Circuit<Fr> + Clone,
+    P: Prover<'params, KZGCommitmentScheme<Bn256>>,
+    V: Verifier<'params, KZGCommitmentScheme<Bn256>>,
+    KZGSingleStrategy<'params, Bn256>: VerificationStrategy<'params, KZGCommitmentScheme<Bn256>, V>,
+{
+    let prover_name = format!("{}-prover", name);
+    let verifier_name = format!("{}-verifier", name);
+    let mut rng = OsRng;
+
+    // Benchmark proof creation
+    c.bench_function(&prover_name, |b| {
+        b.iter(|| {
+            let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+            create_proof::<KZGCommitmentScheme<Bn256>, P, _, _, _, _>(
+                params,
+                pk,
+                &[circuit.clone()],
+                &[&[public_input]],
+                &mut rng,
+                &mut transcript,
+            )
+            .expect("proof generation should not fail");
+        });
+    });
+
+    // Create the proof that will be verified
+    let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+    create_proof::<KZGCommitmentScheme<Bn256>, P, _, _, _, _>(
+        params,
+        pk,
+        &[circuit],
+        &[&[public_input]],
+        &mut rng,
+        &mut transcript,
+    ).expect("proof generation should not fail");
+    let proof = transcript.finalize();
+    println!("{} proof length: {}", name, proof.len());
+
+    // Benchmark the verifier
+    c.bench_function(&verifier_name, |b| {
+        b.iter(|| {
+            let strategy = KZGSingleStrategy::new(params);
+            let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+            assert!(verify_proof::<KZGCommitmentScheme<Bn256>, V, _, _, _>(
+                params,
+                pk.get_vk(),
+                strategy,
+                &[&[public_input]],
                 &mut transcript
             )
             .is_ok());
@@ -129,11 +380,27 @@ fn bench_merkle(depth: usize, degree: u32, c: &mut Criterion) {
 
 fn criterion_benchmark(c: &mut Criterion) {
     // Depth 22
-    bench_merkle(22 as usize, 10, c);
+    bench_merkle::<vesta::Affine>("MT", 22 as usize, 10, c);
     // Depth 24
-    bench_merkle(24 as usize, 10, c);
+    bench_merkle::<vesta::Affine>("MT", 24 as usize, 10, c);
     // Depth 26 needs higher degree polynomials because of the number of rows
-    bench_merkle(26 as usize, 11, c);
+    bench_merkle::<vesta::Affine>("MT", 26 as usize, 11, c);
+
+    // The same trees over `Fq`, proven on the other curve of the cycle
+    bench_merkle::<pallas::Affine>("MT-Fq", 22, 10, c);
+    bench_merkle::<pallas::Affine>("MT-Fq", 24, 10, c);
+    bench_merkle::<pallas::Affine>("MT-Fq", 26, 11, c);
+
+    // Arity-4 trees with the same number of leaves as the binary ones above
+    bench_merkle_with::<P128Pow5T5, 5, 4, 4>(11, 10, c);
+    bench_merkle_with::<P128Pow5T5, 5, 4, 4>(12, 10, c);
+    bench_merkle_with::<P128Pow5T5, 5, 4, 4>(13, 11, c);
+
+    // The same binary trees over BN254 with KZG. Poseidon has two more partial rounds over this field, which pushes
+    // depth 24 over the rows of degree 10.
+    bench_merkle_kzg(22, 10, c);
+    bench_merkle_kzg(24, 11, c);
+    bench_merkle_kzg(26, 11, c);
 }
 
 criterion_group!(name = benches;
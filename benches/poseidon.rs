@@ -2,21 +2,27 @@ use ff::Field;
 use halo2_proofs::{
     circuit::{Value},
     plonk::{
-        create_proof, keygen_pk, keygen_vk, verify_proof,
+        create_proof, keygen_pk, keygen_vk, verify_proof, ProvingKey,
     },
     poly::{
-        commitment::ParamsProver,
+        commitment::{ParamsProver, Prover, Verifier},
         ipa::{
             commitment::{IPACommitmentScheme, ParamsIPA},
             multiopen::ProverIPA,
             strategy::SingleStrategy,
         },
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::{ProverGWC, ProverSHPLONK, VerifierGWC, VerifierSHPLONK},
+            strategy::SingleStrategy as KZGSingleStrategy,
+        },
         VerificationStrategy,
     },
     transcript::{
         Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
     },
 };
+use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine};
 use halo2curves::pasta::{pallas, vesta, EqAffine, Fp};
 
 use halo2_gadgets::poseidon::{
@@ -39,7 +45,7 @@ fn bench_poseidon<const L: usize>(name: &str, c: &mut Criterion) {
     let params: ParamsIPA<vesta::Affine> = ParamsIPA::new(K);
 
     // We'll use the PoseidonCircuit for OrchardNullifier directly now
-    let empty_circuit = PoseidonCircuit::<OrchardNullifier, 3, 2, L> {
+    let empty_circuit = PoseidonCircuit::<Fp, OrchardNullifier, 3, 2, L> {
         message: (0..L)
             .map(|_| Value::unknown())
             .collect::<Vec<_>>()
@@ -65,7 +71,7 @@ fn bench_poseidon<const L: usize>(name: &str, c: &mut Criterion) {
     let output =
         poseidon::Hash::<_, OrchardNullifier, ConstantLength<L>, 3, 2>::init().hash(message);
 
-    let circuit = PoseidonCircuit::<OrchardNullifier, 3, 2, L> {
+    let circuit = PoseidonCircuit::<Fp, OrchardNullifier, 3, 2, L> {
         message: message.map(|x| Value::known(x)),
         output: Value::known(output),
         _spec: PhantomData,
@@ -88,7 +94,7 @@ fn bench_poseidon<const L: usize>(name: &str, c: &mut Criterion) {
         });
     });
 
-    let circuit = PoseidonCircuit::<OrchardNullifier, 3, 2, L> {
+    let circuit = PoseidonCircuit::<Fp, OrchardNullifier, 3, 2, L> {
         message: message.map(|x| Value::known(x)),
         output: Value::known(output),
         _spec: PhantomData,
@@ -123,8 +129,110 @@ fn bench_poseidon<const L: usize>(name: &str, c: &mut Criterion) {
     });
 }
 
+/// Same as `bench_poseidon`, over BN254 with KZG commitments, opened with both SHPLONK and GWC
+fn bench_poseidon_kzg<const L: usize>(name: &str, c: &mut Criterion) {
+    let mut rng = OsRng;
+    let params = ParamsKZG::<Bn256>::setup(K, &mut rng);
+
+    let empty_circuit = PoseidonCircuit::<Fr, OrchardNullifier, 3, 2, L> {
+        message: [Value::unknown(); L],
+        output: Value::unknown(),
+        _spec: PhantomData,
+    };
+    let vk = keygen_vk(&params, &empty_circuit).expect("keygen_vk should not fail");
+    let pk = keygen_pk(&params, vk, &empty_circuit).expect("keygen_pk should not fail");
+
+    let message: [Fr; L] = [(); L].map(|_| Fr::random(&mut rng));
+    let output =
+        poseidon::Hash::<_, OrchardNullifier, ConstantLength<L>, 3, 2>::init().hash(message);
+    let circuit = PoseidonCircuit::<Fr, OrchardNullifier, 3, 2, L> {
+        message: message.map(|x| Value::known(x)),
+        output: Value::known(output),
+        _spec: PhantomData,
+    };
+
+    bench_kzg::<_, ProverSHPLONK<_>, VerifierSHPLONK<_>>(
+        &format!("{}-kzg-shplonk", name),
+        &params,
+        &pk,
+        circuit,
+        &[output],
+        c,
+    );
+    bench_kzg::<_, ProverGWC<_>, VerifierGWC<_>>(
+        &format!("{}-kzg-gwc", name),
+        &params,
+        &pk,
+        circuit,
+        &[output],
+        c,
+    );
+}
+
+/// Benchmark proving and verifying `circuit` with the KZG multiopen scheme of the prover `P` and verifier `V`
+fn bench_kzg<'params, const L: usize, P, V>(
+    name: &str,
+    params: &'params ParamsKZG<Bn256>,
+    pk: &ProvingKey<G1Affine>,
+    circuit: PoseidonCircuit<Fr, OrchardNullifier, 3, 2, L>,
+    public_input: &[Fr],
+    c: &mut Criterion,
+) where
+    P: Prover<'params, KZGCommitmentScheme<Bn256>>,
+    V: Verifier<'params, KZGCommitmentScheme<Bn256>>,
+    KZGSingleStrategy<'params, Bn256>: VerificationStrategy<'params, KZGCommitmentScheme<Bn256>, V>,
+{
+    let prover_name = format!("{}-prover", name);
+    let verifier_name = format!("{}-verifier", name);
+    let mut rng = OsRng;
+
+    c.bench_function(&prover_name, |b| {
+        b.iter(|| {
+            let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+            create_proof::<KZGCommitmentScheme<Bn256>, P, _, _, _, _>(
+                params,
+                pk,
+                &[circuit],
+                &[&[public_input]],
+                &mut rng,
+                &mut transcript,
+            )
+            .expect("proof generation should not fail");
+        });
+    });
+
+    // Create a proof for the verifier
+    let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+    create_proof::<KZGCommitmentScheme<Bn256>, P, _, _, _, _>(
+        params,
+        pk,
+        &[circuit],
+        &[&[public_input]],
+        &mut rng,
+        &mut transcript,
+    )
+    .expect("proof generation should not fail");
+    let proof = transcript.finalize();
+
+    c.bench_function(&verifier_name, |b| {
+        b.iter(|| {
+            let strategy = KZGSingleStrategy::new(params);
+            let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+            assert!(verify_proof::<KZGCommitmentScheme<Bn256>, V, _, _, _>(
+                params,
+                pk.get_vk(),
+                strategy,
+                &[&[public_input]],
+                &mut transcript
+            )
+            .is_ok());
+        });
+    });
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     bench_poseidon::<2>("L = 2", c);
+    bench_poseidon_kzg::<2>("L = 2", c);
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -1,2 +1,4 @@
+pub mod arithmetic;
+pub mod compare;
 pub mod merkle;
 pub mod poseidon;
@@ -0,0 +1,289 @@
+/*
+A chip for the plain field arithmetic that circuits occasionally need next to hashing, and the running-sum
+decomposition that range checks values and extracts the digits of positions. Nothing in their configuration depends on
+the field, so the chips work over any `F: PrimeField`.
+*/
+
+use ff::PrimeField;
+use halo2_proofs::{
+    circuit::{AssignedCell, Layouter, Value},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Selector},
+    poly::Rotation,
+};
+
+#[derive(Debug, Clone)]
+pub struct ArithmeticConfig {
+    pub advice: [Column<Advice>; 3],
+    pub mul_add_selector: Selector,
+    pub select_selector: Selector,
+    pub bool_selector: Selector,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArithmeticChip {
+    config: ArithmeticConfig,
+}
+
+impl ArithmeticChip {
+    pub fn construct(config: ArithmeticConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn configure<F: PrimeField>(
+        meta: &mut ConstraintSystem<F>,
+        advice: [Column<Advice>; 3],
+    ) -> ArithmeticConfig {
+        let col_a = advice[0];
+        let col_b = advice[1];
+        let col_c = advice[2];
+        let mul_add_selector = meta.selector();
+        let select_selector = meta.selector();
+        let bool_selector = meta.selector();
+        meta.enable_equality(col_a);
+        meta.enable_equality(col_b);
+        meta.enable_equality(col_c);
+
+        // Enforces that the next a is a + b * c.
+        meta.create_gate("mul add", |meta| {
+            let s = meta.query_selector(mul_add_selector);
+            let a = meta.query_advice(col_a, Rotation::cur());
+            let b = meta.query_advice(col_b, Rotation::cur());
+            let c = meta.query_advice(col_c, Rotation::cur());
+            let out = meta.query_advice(col_a, Rotation::next());
+            vec![s * (out - a - b * c)]
+        });
+
+        // Enforces that the next a is a if c is 1, and b if c is 0.
+        meta.create_gate("select", |meta| {
+            let s = meta.query_selector(select_selector);
+            let a = meta.query_advice(col_a, Rotation::cur());
+            let b = meta.query_advice(col_b, Rotation::cur());
+            let c = meta.query_advice(col_c, Rotation::cur());
+            let out = meta.query_advice(col_a, Rotation::next());
+            vec![s * (out - b.clone() - c * (a - b))]
+        });
+
+        // Enforces that a is either a 0 or 1.
+        meta.create_gate("bool", |meta| {
+            let s = meta.query_selector(bool_selector);
+            let a = meta.query_advice(col_a, Rotation::cur());
+            vec![s * a.clone() * (Expression::Constant(F::ONE) - a)]
+        });
+
+        ArithmeticConfig {
+            advice: [col_a, col_b, col_c],
+            mul_add_selector,
+            select_selector,
+            bool_selector,
+        }
+    }
+
+    /// Returns `a + b * c`
+    pub fn mul_add<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        a: &AssignedCell<F, F>,
+        b: &AssignedCell<F, F>,
+        c: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        layouter.assign_region(
+            || "mul add",
+            |mut region| {
+                a.copy_advice(|| "a", &mut region, self.config.advice[0], 0)?;
+                b.copy_advice(|| "b", &mut region, self.config.advice[1], 0)?;
+                c.copy_advice(|| "c", &mut region, self.config.advice[2], 0)?;
+                self.config.mul_add_selector.enable(&mut region, 0)?;
+
+                let out = a
+                    .value()
+                    .zip(b.value())
+                    .zip(c.value())
+                    .map(|((a, b), c)| *a + *b * *c);
+                region.assign_advice(|| "a + b * c", self.config.advice[0], 1, || out)
+            },
+        )
+    }
+
+    /// Returns `a` if `condition` is 1 and `b` if it is 0. `condition` has to be constrained to be boolean elsewhere.
+    pub fn select<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        condition: &AssignedCell<F, F>,
+        a: &AssignedCell<F, F>,
+        b: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        layouter.assign_region(
+            || "select",
+            |mut region| {
+                a.copy_advice(|| "a", &mut region, self.config.advice[0], 0)?;
+                b.copy_advice(|| "b", &mut region, self.config.advice[1], 0)?;
+                condition.copy_advice(|| "condition", &mut region, self.config.advice[2], 0)?;
+                self.config.select_selector.enable(&mut region, 0)?;
+
+                let out = a
+                    .value()
+                    .zip(b.value())
+                    .zip(condition.value())
+                    .map(|((a, b), condition)| *b + *condition * (*a - *b));
+                region.assign_advice(|| "a or b", self.config.advice[0], 1, || out)
+            },
+        )
+    }
+
+    /// Constrains `value` to be either 0 or 1
+    pub fn assert_bool<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "assert bool",
+            |mut region| {
+                value.copy_advice(|| "value", &mut region, self.config.advice[0], 0)?;
+                self.config.bool_selector.enable(&mut region, 0)
+            },
+        )
+    }
+}
+
+/// The configuration of a `RunningSumChip`: the column the running sum is kept in, the column of the digits it
+/// absorbs, and the base of the digits.
+#[derive(Debug, Clone)]
+pub struct RunningSumConfig {
+    pub acc: Column<Advice>,
+    pub digit: Column<Advice>,
+    pub base: usize,
+    pub digit_selector: Selector,
+    pub decompose_selector: Selector,
+}
+
+/// Decomposes values into digits in a power of two base with a running sum, one digit per row. This is how
+/// `ComparisonChip` range checks values, and how `MerkleTreeChip` gets the positions of a leaf from its index.
+#[derive(Debug, Clone)]
+pub struct RunningSumChip {
+    config: RunningSumConfig,
+}
+
+impl RunningSumChip {
+    pub fn construct(config: RunningSumConfig) -> Self {
+        Self { config }
+    }
+
+    /// Configure the chip to keep the running sum in `acc` and the digits in `digit`. The digit gate has degree
+    /// `base + 1`. `decompose` starts the running sum from a constant, so the circuit must have a constant column.
+    pub fn configure<F: PrimeField>(
+        meta: &mut ConstraintSystem<F>,
+        acc: Column<Advice>,
+        digit: Column<Advice>,
+        base: usize,
+    ) -> RunningSumConfig {
+        assert!(
+            base >= 2 && base.is_power_of_two(),
+            "unsupported base {}",
+            base
+        );
+        let digit_selector = meta.selector();
+        let decompose_selector = meta.selector();
+        meta.enable_equality(acc);
+        meta.enable_equality(digit);
+
+        // Enforces that the digit is one of 0..base, a bit for base 2.
+        meta.create_gate("digit", |meta| {
+            let s = meta.query_selector(digit_selector);
+            let digit = meta.query_advice(digit, Rotation::cur());
+            vec![(0..base).fold(s, |product, k| {
+                product * (digit.clone() - Expression::Constant(F::from(k as u64)))
+            })]
+        });
+
+        // Enforces that the running sum absorbs the digit on every row: acc_next = base * acc + digit.
+        meta.create_gate("decompose", |meta| {
+            let s = meta.query_selector(decompose_selector);
+            let acc_cur = meta.query_advice(acc, Rotation::cur());
+            let digit = meta.query_advice(digit, Rotation::cur());
+            let acc_next = meta.query_advice(acc, Rotation::next());
+            vec![s * (acc_next - acc_cur * Expression::Constant(F::from(base as u64)) - digit)]
+        });
+
+        RunningSumConfig {
+            acc,
+            digit,
+            base,
+            digit_selector,
+            decompose_selector,
+        }
+    }
+
+    /// Decompose `value` into `num_digits` digits, least significant digit first, and constrain them to add up to
+    /// `value`. This also range checks `value` to `[0, base^num_digits)`. The digits have to fit in less bits than the
+    /// field has so that the decomposition is unique.
+    pub fn decompose<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+        num_digits: usize,
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        let base = self.config.base;
+        let digit_bits = base.trailing_zeros() as usize;
+        assert!(
+            num_digits * digit_bits < F::NUM_BITS as usize,
+            "cannot uniquely decompose into {} digits",
+            num_digits
+        );
+        let digits: Value<Vec<u64>> = value.value().map(|x| {
+            let repr = x.to_repr();
+            (0..num_digits)
+                .map(|i| {
+                    (0..digit_bits)
+                        .map(|j| {
+                            let bit = i * digit_bits + j;
+                            (((repr.as_ref()[bit / 8] >> (bit % 8)) & 1) as u64) << j
+                        })
+                        .sum()
+                })
+                .collect()
+        });
+
+        layouter.assign_region(
+            || "decompose",
+            |mut region| {
+                // The running sum starts at zero and absorbs one digit per row, most significant digit first
+                let mut acc = region.assign_advice_from_constant(
+                    || "running sum",
+                    self.config.acc,
+                    0,
+                    F::ZERO,
+                )?;
+                let mut cells = Vec::with_capacity(num_digits);
+                for row in 0..num_digits {
+                    let i = num_digits - 1 - row;
+                    let digit = digits.as_ref().map(|digits| F::from(digits[i]));
+                    let digit_cell = region.assign_advice(
+                        || format!("digit {}", i),
+                        self.config.digit,
+                        row,
+                        || digit,
+                    )?;
+                    self.config.digit_selector.enable(&mut region, row)?;
+                    self.config.decompose_selector.enable(&mut region, row)?;
+
+                    let acc_value = acc
+                        .value()
+                        .zip(digit)
+                        .map(|(acc, digit)| *acc * F::from(base as u64) + digit);
+                    acc = region.assign_advice(
+                        || "running sum",
+                        self.config.acc,
+                        row + 1,
+                        || acc_value,
+                    )?;
+                    cells.push(digit_cell);
+                }
+                region.constrain_equal(acc.cell(), value.cell())?;
+
+                cells.reverse();
+                Ok(cells)
+            },
+        )
+    }
+}
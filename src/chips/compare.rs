@@ -0,0 +1,355 @@
+/*
+A chip comparing field elements as the integers in [0, p) they stand for. Values are split into a 128-bit low limb and
+a high limb holding the remaining bits, and the split is checked to be the canonical one, below the modulus. Limbs are
+small enough that the differences the chip range checks cannot wrap around the modulus, so the comparison covers the
+whole field. Like the arithmetic chip, it works over any `F: PrimeField` of more than 128 bits.
+*/
+
+use ff::{Field, PrimeField};
+use halo2_proofs::{
+    circuit::{AssignedCell, Layouter},
+    plonk::{Advice, Column, ConstraintSystem, Error, Expression, Selector},
+    poly::Rotation,
+};
+
+use super::arithmetic::{RunningSumChip, RunningSumConfig};
+
+/// The number of bits of the low limb of a value
+pub const LIMB_BITS: usize = 128;
+
+/// A value split into limbs, `value = hi * 2^128 + lo`
+#[derive(Debug, Clone)]
+pub struct Limbs<F: PrimeField> {
+    pub lo: AssignedCell<F, F>,
+    pub hi: AssignedCell<F, F>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparisonConfig {
+    pub advice: [Column<Advice>; 3],
+    pub running_sum_config: RunningSumConfig,
+    pub split_selector: Selector,
+    pub difference_selector: Selector,
+    pub or_max_selector: Selector,
+}
+
+#[derive(Debug, Clone)]
+pub struct ComparisonChip {
+    config: ComparisonConfig,
+}
+
+impl ComparisonChip {
+    pub fn construct(config: ComparisonConfig) -> Self {
+        Self { config }
+    }
+
+    /// `decompose` loads the limbs of the modulus and starts running sums from constants, so the circuit must have a
+    /// constant column, e.g. the one `PoseidonChip::configure` enables.
+    pub fn configure<F: PrimeField>(
+        meta: &mut ConstraintSystem<F>,
+        advice: [Column<Advice>; 3],
+    ) -> ComparisonConfig {
+        // Both limbs have to fit in a u128, and their differences must stay far below the modulus
+        assert!(
+            F::NUM_BITS as usize > LIMB_BITS && F::NUM_BITS as usize <= 2 * LIMB_BITS,
+            "cannot compare {}-bit values",
+            F::NUM_BITS
+        );
+
+        let col_a = advice[0];
+        let col_b = advice[1];
+        let col_c = advice[2];
+        let split_selector = meta.selector();
+        let difference_selector = meta.selector();
+        let or_max_selector = meta.selector();
+        meta.enable_equality(col_a);
+        meta.enable_equality(col_b);
+        meta.enable_equality(col_c);
+
+        // Range checks keep the running sum in a and its bits in c
+        let running_sum_config = RunningSumChip::configure(meta, col_a, col_c, 2);
+
+        // Enforces that a = b * 2^128 + c.
+        meta.create_gate("split", |meta| {
+            let s = meta.query_selector(split_selector);
+            let value = meta.query_advice(col_a, Rotation::cur());
+            let hi = meta.query_advice(col_b, Rotation::cur());
+            let lo = meta.query_advice(col_c, Rotation::cur());
+            vec![s * (value - hi * Expression::Constant(limb_base::<F>()) - lo)]
+        });
+
+        // The low limbs of a and b are on the first row and their high limbs on the second one, with the difference of
+        // the limbs in c. Enforces that the borrow on the third row is a bit, and that the limbs of c add up to
+        // b - a - 1, which is non-negative exactly when a < b once both limbs of c are range checked.
+        meta.create_gate("difference", |meta| {
+            let s = meta.query_selector(difference_selector);
+            let a_lo = meta.query_advice(col_a, Rotation::cur());
+            let b_lo = meta.query_advice(col_b, Rotation::cur());
+            let c_lo = meta.query_advice(col_c, Rotation::cur());
+            let a_hi = meta.query_advice(col_a, Rotation::next());
+            let b_hi = meta.query_advice(col_b, Rotation::next());
+            let c_hi = meta.query_advice(col_c, Rotation::next());
+            let borrow = meta.query_advice(col_a, Rotation(2));
+            vec![
+                s.clone() * borrow.clone() * (Expression::Constant(F::ONE) - borrow.clone()),
+                s.clone()
+                    * (c_lo - b_lo + a_lo + Expression::Constant(F::ONE)
+                        - borrow.clone() * Expression::Constant(limb_base::<F>())),
+                s * (c_hi - b_hi + a_hi + borrow),
+            ]
+        });
+
+        // Enforces that c is 1 if a is zero and 0 otherwise, with b holding the inverse of a if any. The limbs of a are
+        // on the second row, and the third one holds them with the limbs of the modulus added if a is zero.
+        meta.create_gate("or max", |meta| {
+            let s = meta.query_selector(or_max_selector);
+            let value = meta.query_advice(col_a, Rotation::cur());
+            let inv = meta.query_advice(col_b, Rotation::cur());
+            let is_zero = meta.query_advice(col_c, Rotation::cur());
+            let lo = meta.query_advice(col_a, Rotation::next());
+            let hi = meta.query_advice(col_b, Rotation::next());
+            let bound_lo = meta.query_advice(col_a, Rotation(2));
+            let bound_hi = meta.query_advice(col_b, Rotation(2));
+            let (modulus_lo, modulus_hi) = modulus_limbs::<F>();
+            vec![
+                s.clone() * value.clone() * is_zero.clone(),
+                s.clone() * (is_zero.clone() - Expression::Constant(F::ONE) + value * inv),
+                s.clone() * (bound_lo - lo - is_zero.clone() * Expression::Constant(modulus_lo)),
+                s * (bound_hi - hi - is_zero * Expression::Constant(modulus_hi)),
+            ]
+        });
+
+        ComparisonConfig {
+            advice: [col_a, col_b, col_c],
+            running_sum_config,
+            split_selector,
+            difference_selector,
+            or_max_selector,
+        }
+    }
+
+    /// Split `value` into its canonical limbs: both are range checked, and the limbs are checked to stand for an
+    /// integer below the modulus, so that no value has a second split.
+    pub fn decompose<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+    ) -> Result<Limbs<F>, Error> {
+        let limbs = value.value().map(|x| {
+            let (lo, hi) = split(x);
+            (F::from_u128(lo), F::from_u128(hi))
+        });
+
+        let (lo, hi) = layouter.assign_region(
+            || "split",
+            |mut region| {
+                value.copy_advice(|| "value", &mut region, self.config.advice[0], 0)?;
+                let hi = region.assign_advice(
+                    || "hi",
+                    self.config.advice[1],
+                    0,
+                    || limbs.map(|(_, hi)| hi),
+                )?;
+                let lo = region.assign_advice(
+                    || "lo",
+                    self.config.advice[2],
+                    0,
+                    || limbs.map(|(lo, _)| lo),
+                )?;
+                self.config.split_selector.enable(&mut region, 0)?;
+                Ok((lo, hi))
+            },
+        )?;
+        self.range_check(layouter.namespace(|| "range check lo"), &lo, LIMB_BITS)?;
+        self.range_check(
+            layouter.namespace(|| "range check hi"),
+            &hi,
+            high_bits::<F>(),
+        )?;
+        let limbs = Limbs { lo, hi };
+
+        let (modulus_lo, modulus_hi) = modulus_limbs::<F>();
+        let modulus = layouter.assign_region(
+            || "load modulus",
+            |mut region| {
+                Ok(Limbs {
+                    lo: region.assign_advice_from_constant(
+                        || "modulus lo",
+                        self.config.advice[0],
+                        0,
+                        modulus_lo,
+                    )?,
+                    hi: region.assign_advice_from_constant(
+                        || "modulus hi",
+                        self.config.advice[1],
+                        0,
+                        modulus_hi,
+                    )?,
+                })
+            },
+        )?;
+        self.less_than(layouter.namespace(|| "canonical"), &limbs, &modulus)?;
+        Ok(limbs)
+    }
+
+    /// Constrain `a < b`. The limbs must come out of `decompose` or `or_max`.
+    pub fn less_than<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        a: &Limbs<F>,
+        b: &Limbs<F>,
+    ) -> Result<(), Error> {
+        let (difference_lo, difference_hi) = layouter.assign_region(
+            || "difference",
+            |mut region| {
+                a.lo.copy_advice(|| "a lo", &mut region, self.config.advice[0], 0)?;
+                b.lo.copy_advice(|| "b lo", &mut region, self.config.advice[1], 0)?;
+                a.hi.copy_advice(|| "a hi", &mut region, self.config.advice[0], 1)?;
+                b.hi.copy_advice(|| "b hi", &mut region, self.config.advice[1], 1)?;
+                self.config.difference_selector.enable(&mut region, 0)?;
+
+                // Borrow from the high limbs if the low limb of b is not larger than the one of a
+                let borrow =
+                    a.lo.value()
+                        .zip(b.lo.value())
+                        .map(|(a, b)| F::from((to_u128(b) <= to_u128(a)) as u64));
+                let borrow =
+                    region.assign_advice(|| "borrow", self.config.advice[0], 2, || borrow)?;
+
+                let difference_lo =
+                    a.lo.value()
+                        .zip(b.lo.value())
+                        .zip(borrow.value())
+                        .map(|((a, b), borrow)| *b - *a - F::ONE + *borrow * limb_base::<F>());
+                let difference_hi =
+                    a.hi.value()
+                        .zip(b.hi.value())
+                        .zip(borrow.value())
+                        .map(|((a, b), borrow)| *b - *a - *borrow);
+                Ok((
+                    region.assign_advice(
+                        || "b lo - a lo - 1",
+                        self.config.advice[2],
+                        0,
+                        || difference_lo,
+                    )?,
+                    region.assign_advice(
+                        || "b hi - a hi",
+                        self.config.advice[2],
+                        1,
+                        || difference_hi,
+                    )?,
+                ))
+            },
+        )?;
+        self.range_check(
+            layouter.namespace(|| "range check difference lo"),
+            &difference_lo,
+            LIMB_BITS,
+        )?;
+        self.range_check(
+            layouter.namespace(|| "range check difference hi"),
+            &difference_hi,
+            high_bits::<F>(),
+        )
+    }
+
+    /// The limbs of `value` if it is non-zero, and of the modulus otherwise. This turns a zero that stands for "no
+    /// upper bound" into a bound every value is below.
+    pub fn or_max<F: PrimeField>(
+        &self,
+        mut layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+        limbs: &Limbs<F>,
+    ) -> Result<Limbs<F>, Error> {
+        layouter.assign_region(
+            || "or max",
+            |mut region| {
+                value.copy_advice(|| "value", &mut region, self.config.advice[0], 0)?;
+                let inv = value.value().map(|x| x.invert().unwrap_or(F::ZERO));
+                region.assign_advice(|| "inverse", self.config.advice[1], 0, || inv)?;
+                let is_zero = value.value().map(|x| F::from((*x == F::ZERO) as u64));
+                region.assign_advice(|| "is zero", self.config.advice[2], 0, || is_zero)?;
+                limbs
+                    .lo
+                    .copy_advice(|| "lo", &mut region, self.config.advice[0], 1)?;
+                limbs
+                    .hi
+                    .copy_advice(|| "hi", &mut region, self.config.advice[1], 1)?;
+                self.config.or_max_selector.enable(&mut region, 0)?;
+
+                let (modulus_lo, modulus_hi) = modulus_limbs::<F>();
+                let bound_lo = limbs
+                    .lo
+                    .value()
+                    .zip(is_zero)
+                    .map(|(lo, is_zero)| *lo + is_zero * modulus_lo);
+                let bound_hi = limbs
+                    .hi
+                    .value()
+                    .zip(is_zero)
+                    .map(|(hi, is_zero)| *hi + is_zero * modulus_hi);
+                Ok(Limbs {
+                    lo: region.assign_advice(
+                        || "bound lo",
+                        self.config.advice[0],
+                        2,
+                        || bound_lo,
+                    )?,
+                    hi: region.assign_advice(
+                        || "bound hi",
+                        self.config.advice[1],
+                        2,
+                        || bound_hi,
+                    )?,
+                })
+            },
+        )
+    }
+
+    // Constrain `value` to fit in `num_bits` bits
+    fn range_check<F: PrimeField>(
+        &self,
+        layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+        num_bits: usize,
+    ) -> Result<(), Error> {
+        RunningSumChip::construct(self.config.running_sum_config.clone())
+            .decompose(layouter, value, num_bits)?;
+        Ok(())
+    }
+}
+
+// 2^128, the weight of the high limb
+fn limb_base<F: PrimeField>() -> F {
+    F::from_u128(u128::MAX) + F::ONE
+}
+
+// The number of bits of the high limb
+fn high_bits<F: PrimeField>() -> usize {
+    F::NUM_BITS as usize - LIMB_BITS
+}
+
+// The limbs of the canonical integer of `value`, as (lo, hi)
+fn split<F: PrimeField>(value: &F) -> (u128, u128) {
+    let repr = value.to_repr();
+    let bytes = repr.as_ref();
+    (
+        u128::from_le_bytes(bytes[..16].try_into().unwrap()),
+        u128::from_le_bytes(bytes[16..32].try_into().unwrap()),
+    )
+}
+
+// A value known to fit in a limb
+fn to_u128<F: PrimeField>(value: &F) -> u128 {
+    split(value).0
+}
+
+// The limbs of the modulus, as (lo, hi). The modulus itself is not a field element, so it is built from p - 1.
+fn modulus_limbs<F: PrimeField>() -> (F, F) {
+    let (lo, hi) = split(&-F::ONE);
+    match lo.checked_add(1) {
+        Some(lo) => (F::from_u128(lo), F::from_u128(hi)),
+        None => (F::ZERO, F::from_u128(hi + 1)),
+    }
+}
@@ -1,7 +1,10 @@
+use super::arithmetic::{RunningSumChip, RunningSumConfig};
 use super::poseidon::{PoseidonChip, PoseidonConfig};
 use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+use ff::{Field, PrimeField};
+use halo2_gadgets::poseidon::primitives::Spec;
 use halo2_proofs::{
-    circuit::{AssignedCell, Layouter, Value},
+    circuit::{AssignedCell, Layouter, Region, Value},
     plonk::{
         Advice, Column,
         ConstraintSystem, Error, Expression, Instance, Selector,
@@ -11,77 +14,169 @@ use halo2_proofs::{
     },
 };
 use halo2curves::pasta::{Fp};
+use std::marker::PhantomData;
 
+/// The children of a node, given the child on the path, its position among the `ARITY` children and its siblings in
+/// order. Positions out of range put the child on the path first, the circuit rejects them anyway.
+pub fn children<F: PrimeField, const ARITY: usize>(
+    digest: F,
+    elements: &[F],
+    position: F,
+) -> [F; ARITY] {
+    assert_eq!(elements.len(), ARITY - 1);
+    let position = (0..ARITY)
+        .position(|p| F::from(p as u64) == position)
+        .unwrap_or(0);
+    let mut children = [F::ZERO; ARITY];
+    for (j, child) in children.iter_mut().enumerate() {
+        *child = if j < position {
+            elements[j]
+        } else if j == position {
+            digest
+        } else {
+            elements[j - 1]
+        };
+    }
+    children
+}
+
+/// The configuration of a `MerkleTreeChip` hashing `ARITY` children per node with the Poseidon `S` of `WIDTH` and
+/// `RATE`, over the field `F`. The defaults are the binary tree over `P128Pow5T3` on Pasta that the rest of the crate
+/// uses.
 #[derive(Debug, Clone)]
-pub struct MerkleTreeConfig {
-    pub advice: [Column<Advice>; 3],
-    pub bool_selector: Selector,
+pub struct MerkleTreeConfig<
+    F: PrimeField = Fp,
+    S = OrchardNullifier,
+    const WIDTH: usize = 3,
+    const RATE: usize = 2,
+    const ARITY: usize = 2,
+> {
+    // The digest, the `ARITY - 1` siblings and the position of a layer, in this order
+    pub advice: Vec<Column<Advice>>,
+    // The digit gate of `running_sum_config`, which also checks the position of every layer
+    pub digit_selector: Selector,
     pub swap_selector: Selector,
+    pub running_sum_config: RunningSumConfig,
     pub instance: Column<Instance>,
-    pub poseidon_config: PoseidonConfig<3, 2, 2>,
+    pub poseidon_config: PoseidonConfig<F, WIDTH, RATE, ARITY>,
+    _spec: PhantomData<S>,
 }
 
 #[derive(Debug, Clone)]
-pub struct MerkleTreeChip {
-    config: MerkleTreeConfig,
+pub struct MerkleTreeChip<
+    F: PrimeField = Fp,
+    S = OrchardNullifier,
+    const WIDTH: usize = 3,
+    const RATE: usize = 2,
+    const ARITY: usize = 2,
+> {
+    config: MerkleTreeConfig<F, S, WIDTH, RATE, ARITY>,
 }
 
-impl MerkleTreeChip {
-    pub fn construct(config: MerkleTreeConfig) -> Self {
+impl<
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE> + Clone,
+        const WIDTH: usize,
+        const RATE: usize,
+        const ARITY: usize,
+    > MerkleTreeChip<F, S, WIDTH, RATE, ARITY>
+{
+    pub fn construct(config: MerkleTreeConfig<F, S, WIDTH, RATE, ARITY>) -> Self {
         Self { config }
     }
 
+    /// Configure the chip over `ARITY + 1` advice columns. `ARITY` has to be a power of two so that positions can be
+    /// decomposed from bits, and the gates have degree `ARITY + 1`.
     pub fn configure(
-        meta: &mut ConstraintSystem<Fp>,
-        advice: [Column<Advice>; 3],
+        meta: &mut ConstraintSystem<F>,
+        advice: &[Column<Advice>],
         instance: Column<Instance>,
-    ) -> MerkleTreeConfig {
-        let col_a = advice[0];
-        let col_b = advice[1];
-        let col_c = advice[2];
-        let bool_selector = meta.selector();
+    ) -> MerkleTreeConfig<F, S, WIDTH, RATE, ARITY> {
+        assert!(
+            ARITY >= 2 && ARITY.is_power_of_two(),
+            "unsupported arity {}",
+            ARITY
+        );
+        assert_eq!(advice.len(), ARITY + 1, "the chip needs ARITY + 1 advice columns");
+        let col_digest = advice[0];
+        let col_position = advice[ARITY];
         let swap_selector = meta.selector();
-        meta.enable_equality(col_a);
-        meta.enable_equality(col_b);
-        meta.enable_equality(col_c);
+        for column in advice {
+            meta.enable_equality(*column);
+        }
         meta.enable_equality(instance);
 
-        // Enforces that c is either a 0 or 1.
-        meta.create_gate("bool", |meta| {
-            let s = meta.query_selector(bool_selector);
-            let c = meta.query_advice(col_c, Rotation::cur());
-            vec![s * c.clone() * (Expression::Constant(Fp::from(1)) - c.clone())]
-        });
+        // Enforces that the position is one of 0..ARITY, a bit for binary trees. Indices are decomposed into positions
+        // with a running sum in the digest column.
+        let running_sum_config = RunningSumChip::configure(meta, col_digest, col_position, ARITY);
 
-        // Enforces that if the swap bit is on, l=b and r=a. Otherwise, l=a and r=b.
+        // Enforces that the next row holds the children in order: the digest at the position, and the siblings around
+        // it. For binary trees, if the swap bit is on, l=b and r=a. Otherwise, l=a and r=b.
         meta.create_gate("swap", |meta| {
             let s = meta.query_selector(swap_selector);
-            let a = meta.query_advice(col_a, Rotation::cur());
-            let b = meta.query_advice(col_b, Rotation::cur());
-            let c = meta.query_advice(col_c, Rotation::cur());
-            let l = meta.query_advice(col_a, Rotation::next());
-            let r = meta.query_advice(col_b, Rotation::next());
-            vec![
-                s * (c * Expression::Constant(Fp::from(2)) * (b.clone() - a.clone())
-                    - (l - a.clone())
-                    - (b.clone() - r)),
-            ]
+            let digest = meta.query_advice(col_digest, Rotation::cur());
+            let elements = advice[1..ARITY]
+                .iter()
+                .map(|column| meta.query_advice(*column, Rotation::cur()))
+                .collect::<Vec<_>>();
+            let position = meta.query_advice(col_position, Rotation::cur());
+
+            // `selectors[p]` is 1 if the position is p, and 0 for the other positions
+            let selectors = (0..ARITY)
+                .map(|p| {
+                    (0..ARITY).filter(|k| *k != p).fold(
+                        Expression::Constant(F::ONE),
+                        |acc, k| {
+                            let denominator = (F::from(p as u64) - F::from(k as u64))
+                                .invert()
+                                .unwrap();
+                            acc * (position.clone() - Expression::Constant(F::from(k as u64)))
+                                * Expression::Constant(denominator)
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // Every child must be pinned down individually: a single combined constraint only fixes their
+            // differences and lets the prover shift all of them by the same amount.
+            (0..ARITY)
+                .map(|j| {
+                    let child = meta.query_advice(advice[j], Rotation::next());
+                    let expected = (0..ARITY)
+                        .map(|p| {
+                            let value = if j < p {
+                                elements[j].clone()
+                            } else if j == p {
+                                digest.clone()
+                            } else {
+                                elements[j - 1].clone()
+                            };
+                            selectors[p].clone() * value
+                        })
+                        .reduce(|acc, x| acc + x)
+                        .unwrap();
+                    s.clone() * (child - expected)
+                })
+                .collect::<Vec<_>>()
         });
 
         MerkleTreeConfig {
-            advice: [col_a, col_b, col_c],
-            bool_selector: bool_selector,
+            advice: advice.to_vec(),
+            digit_selector: running_sum_config.digit_selector,
             swap_selector: swap_selector,
+            running_sum_config: running_sum_config,
             instance: instance,
-            poseidon_config: PoseidonChip::<OrchardNullifier, 3, 2, 2>::configure(meta),
+            // The poseidon digests are never exposed directly, our own instance column takes care of that
+            poseidon_config: PoseidonChip::<F, S, WIDTH, RATE, ARITY>::configure(meta, None),
+            _spec: PhantomData,
         }
     }
 
     pub fn load_private(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        input: Value<Fp>,
-    ) -> Result<AssignedCell<Fp, Fp>, Error> {
+        mut layouter: impl Layouter<F>,
+        input: Value<F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
         layouter.assign_region(
             || "load private",
             |mut region| {
@@ -92,9 +187,9 @@ impl MerkleTreeChip {
 
     pub fn load_constant(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        constant: Fp,
-    ) -> Result<AssignedCell<Fp, Fp>, Error> {
+        mut layouter: impl Layouter<F>,
+        constant: F,
+    ) -> Result<AssignedCell<F, F>, Error> {
         layouter.assign_region(
             || "load constant",
             |mut region| {
@@ -110,72 +205,337 @@ impl MerkleTreeChip {
 
     pub fn expose_public(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        cell: &AssignedCell<Fp, Fp>,
+        mut layouter: impl Layouter<F>,
+        cell: &AssignedCell<F, F>,
         row: usize,
     ) -> Result<(), Error> {
         layouter.constrain_instance(cell.cell(), self.config.instance, row)
     }
 
+    pub fn constrain_equal(
+        &self,
+        mut layouter: impl Layouter<F>,
+        a: &AssignedCell<F, F>,
+        b: &AssignedCell<F, F>,
+    ) -> Result<(), Error> {
+        layouter.assign_region(
+            || "constrain equal",
+            |mut region| region.constrain_equal(a.cell(), b.cell()),
+        )
+    }
+
+    /// Decompose `value` into `num_digits` digits in base `ARITY` (bits for binary trees), least significant digit
+    /// first, and constrain them to add up to `value`. This also range checks `value` to `[0, ARITY^num_digits)`. The
+    /// digits have to fit in less bits than the field has so that the decomposition is unique.
+    pub fn decompose(
+        &self,
+        layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+        num_digits: usize,
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        RunningSumChip::construct(self.config.running_sum_config.clone()).decompose(
+            layouter,
+            value,
+            num_digits,
+        )
+    }
+
+    /// Hash `digest` with its `ARITY - 1` siblings `elements` into the next digest. `index` is the position of
+    /// `digest` among the children.
     pub fn merkle_prove_layer(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        digest: &AssignedCell<Fp, Fp>,
-        element: Value<Fp>,
-        index: Value<Fp>,
-    ) -> Result<AssignedCell<Fp, Fp>, Error> {
-        let (left, right) = layouter.assign_region(
+        layouter: impl Layouter<F>,
+        digest: &AssignedCell<F, F>,
+        elements: &[Value<F>],
+        index: Value<F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        self.swap_and_hash(
+            layouter,
+            digest,
+            |region| self.assign_elements(region, elements),
+            |region| region.assign_advice(|| "index", self.config.advice[ARITY], 0, || index),
+        )
+    }
+
+    /// Same as `merkle_prove_layer`, but the index is a cell that was already assigned elsewhere (e.g. a bit coming
+    /// out of `decompose`) and gets copied into the layer.
+    pub fn merkle_prove_layer_with_bit(
+        &self,
+        layouter: impl Layouter<F>,
+        digest: &AssignedCell<F, F>,
+        elements: &[Value<F>],
+        bit: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        self.swap_and_hash(
+            layouter,
+            digest,
+            |region| self.assign_elements(region, elements),
+            |region| bit.copy_advice(|| "index", region, self.config.advice[ARITY], 0),
+        )
+    }
+
+    /// Same as `merkle_prove_layer_with_bit`, but the siblings are cells as well. This lets several paths be
+    /// constrained to share their siblings.
+    pub fn merkle_prove_layer_with_cells(
+        &self,
+        layouter: impl Layouter<F>,
+        digest: &AssignedCell<F, F>,
+        elements: &[AssignedCell<F, F>],
+        bit: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        assert_eq!(elements.len(), ARITY - 1);
+        self.swap_and_hash(
+            layouter,
+            digest,
+            |region| {
+                elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| {
+                        element.copy_advice(
+                            || format!("element {}", i),
+                            region,
+                            self.config.advice[i + 1],
+                            0,
+                        )
+                    })
+                    .collect()
+            },
+            |region| bit.copy_advice(|| "index", region, self.config.advice[ARITY], 0),
+        )
+    }
+
+    fn assign_elements(
+        &self,
+        region: &mut Region<'_, F>,
+        elements: &[Value<F>],
+    ) -> Result<Vec<AssignedCell<F, F>>, Error> {
+        assert_eq!(elements.len(), ARITY - 1);
+        elements
+            .iter()
+            .enumerate()
+            .map(|(i, element)| {
+                region.assign_advice(
+                    || format!("element {}", i),
+                    self.config.advice[i + 1],
+                    0,
+                    || *element,
+                )
+            })
+            .collect()
+    }
+
+    fn swap_and_hash(
+        &self,
+        mut layouter: impl Layouter<F>,
+        digest: &AssignedCell<F, F>,
+        assign_elements: impl Fn(&mut Region<'_, F>) -> Result<Vec<AssignedCell<F, F>>, Error>,
+        assign_index: impl Fn(&mut Region<'_, F>) -> Result<AssignedCell<F, F>, Error>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let children = layouter.assign_region(
             || "merkle_prove_leaf",
             |mut region| {
                 // Row 0
                 digest.copy_advice(|| "digest", &mut region, self.config.advice[0], 0)?;
-                region.assign_advice(|| "element", self.config.advice[1], 0, || element)?;
-                region.assign_advice(|| "index", self.config.advice[2], 0, || index)?;
-                self.config.bool_selector.enable(&mut region, 0)?;
+                let elements = assign_elements(&mut region)?;
+                let index = assign_index(&mut region)?;
+                self.config.digit_selector.enable(&mut region, 0)?;
                 self.config.swap_selector.enable(&mut region, 0)?;
 
                 // Row 1
-                let digest_value = digest.value().map(|x| x.to_owned());
-                let (mut l, mut r) = (digest_value, element);
-                index.map(|x| {
-                    (l, r) = if x == Fp::zero() { (l, r) } else { (r, l) };
-                });
-                let left = region.assign_advice(|| "left", self.config.advice[0], 1, || l)?;
-                let right = region.assign_advice(|| "right", self.config.advice[1], 1, || r)?;
-
-                Ok((left, right))
+                let elements: Value<Vec<F>> = elements.iter().map(|x| x.value().copied()).collect();
+                let children = digest
+                    .value()
+                    .zip(elements)
+                    .zip(index.value())
+                    .map(|((digest, elements), index)| {
+                        children::<F, ARITY>(*digest, &elements, *index)
+                    });
+                (0..ARITY)
+                    .map(|i| {
+                        region.assign_advice(
+                            || format!("child {}", i),
+                            self.config.advice[i],
+                            1,
+                            || children.map(|children| children[i]),
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()
             },
         )?;
 
-        let poseidon_chip = PoseidonChip::<OrchardNullifier, 3, 2, 2>::construct(
+        let poseidon_chip = PoseidonChip::<F, S, WIDTH, RATE, ARITY>::construct(
             self.config.poseidon_config.clone(),
         );
-        let digest = poseidon_chip.hash(layouter.namespace(|| "poseidon"), &[left, right])?;
+        let digest = poseidon_chip.hash(
+            layouter.namespace(|| "poseidon"),
+            &children.try_into().unwrap(),
+        )?;
         Ok(digest)
     }
 
+    /// Compute the root of the path from `leaf`. Every layer has an index and `ARITY - 1` consecutive `elements`.
     pub fn merkle_prove(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        leaf: &AssignedCell<Fp, Fp>,
-        elements: &Vec<Value<Fp>>,
-        indices: &Vec<Value<Fp>>,
-    ) -> Result<AssignedCell<Fp, Fp>, Error> {
-        let layers = elements.len();
-        let mut leaf_or_digest = self.merkle_prove_layer(
-            layouter.namespace(|| "merkle_prove_layer_0"),
-            leaf,
-            elements[0],
-            indices[0],
-        )?;
-        for i in 1..layers {
+        mut layouter: impl Layouter<F>,
+        leaf: &AssignedCell<F, F>,
+        elements: &Vec<Value<F>>,
+        indices: &Vec<Value<F>>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        assert_eq!(elements.len(), indices.len() * (ARITY - 1));
+        let mut leaf_or_digest = leaf.clone();
+        for (i, (elements, index)) in elements.chunks(ARITY - 1).zip(indices).enumerate() {
             leaf_or_digest = self.merkle_prove_layer(
                 layouter.namespace(|| format!("merkle_prove_layer_{}", i)),
                 &leaf_or_digest,
-                elements[i],
-                indices[i],
+                elements,
+                *index,
             )?;
         }
         Ok(leaf_or_digest)
     }
+
+    /// Same as `merkle_prove`, but the path directions are cells that were already assigned elsewhere
+    pub fn merkle_prove_with_bits(
+        &self,
+        mut layouter: impl Layouter<F>,
+        leaf: &AssignedCell<F, F>,
+        elements: &Vec<Value<F>>,
+        bits: &[AssignedCell<F, F>],
+    ) -> Result<AssignedCell<F, F>, Error> {
+        assert_eq!(elements.len(), bits.len() * (ARITY - 1));
+        let mut leaf_or_digest = leaf.clone();
+        for (i, (elements, bit)) in elements.chunks(ARITY - 1).zip(bits).enumerate() {
+            leaf_or_digest = self.merkle_prove_layer_with_bit(
+                layouter.namespace(|| format!("merkle_prove_layer_{}", i)),
+                &leaf_or_digest,
+                elements,
+                bit,
+            )?;
+        }
+        Ok(leaf_or_digest)
+    }
+
+    /// Same as `merkle_prove_with_bits`, but the siblings are cells as well
+    pub fn merkle_prove_with_cells(
+        &self,
+        mut layouter: impl Layouter<F>,
+        leaf: &AssignedCell<F, F>,
+        elements: &[AssignedCell<F, F>],
+        bits: &[AssignedCell<F, F>],
+    ) -> Result<AssignedCell<F, F>, Error> {
+        assert_eq!(elements.len(), bits.len() * (ARITY - 1));
+        let mut leaf_or_digest = leaf.clone();
+        for (i, (elements, bit)) in elements.chunks(ARITY - 1).zip(bits).enumerate() {
+            leaf_or_digest = self.merkle_prove_layer_with_cells(
+                layouter.namespace(|| format!("merkle_prove_layer_{}", i)),
+                &leaf_or_digest,
+                elements,
+                bit,
+            )?;
+        }
+        Ok(leaf_or_digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use halo2_proofs::{
+        circuit::SimpleFloorPlanner,
+        dev::MockProver,
+        plonk::Circuit,
+    };
+
+    // Lays out a single swap row like `merkle_prove_layer` does, but with the outputs picked by the test instead of
+    // being computed from the inputs. This is what a malicious prover can do.
+    #[derive(Clone, Default)]
+    struct SwapCircuit {
+        digest: Value<Fp>,
+        element: Value<Fp>,
+        index: Value<Fp>,
+        left: Value<Fp>,
+        right: Value<Fp>,
+    }
+
+    impl Circuit<Fp> for SwapCircuit {
+        type Config = MerkleTreeConfig;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self::default()
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let col_a = meta.advice_column();
+            let col_b = meta.advice_column();
+            let col_c = meta.advice_column();
+            let instance = meta.instance_column();
+            MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance)
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            layouter.assign_region(
+                || "swap",
+                |mut region| {
+                    region.assign_advice(|| "digest", config.advice[0], 0, || self.digest)?;
+                    region.assign_advice(|| "element", config.advice[1], 0, || self.element)?;
+                    region.assign_advice(|| "index", config.advice[2], 0, || self.index)?;
+                    config.digit_selector.enable(&mut region, 0)?;
+                    config.swap_selector.enable(&mut region, 0)?;
+
+                    region.assign_advice(|| "left", config.advice[0], 1, || self.left)?;
+                    region.assign_advice(|| "right", config.advice[1], 1, || self.right)?;
+                    Ok(())
+                },
+            )
+        }
+    }
+
+    fn run(digest: u64, element: u64, index: u64, left: u64, right: u64) -> MockProver<Fp> {
+        let circuit = SwapCircuit {
+            digest: Value::known(Fp::from(digest)),
+            element: Value::known(Fp::from(element)),
+            index: Value::known(Fp::from(index)),
+            left: Value::known(Fp::from(left)),
+            right: Value::known(Fp::from(right)),
+        };
+        MockProver::run(8, &circuit, vec![vec![]]).unwrap()
+    }
+
+    #[test]
+    fn test_honest_swap() {
+        run(3, 7, 0, 3, 7).assert_satisfied();
+        run(3, 7, 1, 7, 3).assert_satisfied();
+    }
+
+    // Shifting both outputs by the same amount kept `l - r` intact and used to satisfy the swap gate
+    #[test]
+    fn test_shifted_outputs() {
+        assert!(run(3, 7, 0, 4, 8).verify().is_err());
+        assert!(run(3, 7, 1, 8, 4).verify().is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_outputs() {
+        // Outputs swapped although the index says they should not be, and the other way around
+        assert!(run(3, 7, 0, 7, 3).verify().is_err());
+        assert!(run(3, 7, 1, 3, 7).verify().is_err());
+        // Only one of the outputs is wrong
+        assert!(run(3, 7, 0, 3, 8).verify().is_err());
+        assert!(run(3, 7, 1, 2, 3).verify().is_err());
+        // Both outputs are the same input
+        assert!(run(3, 7, 0, 3, 3).verify().is_err());
+        assert!(run(3, 7, 1, 7, 7).verify().is_err());
+    }
+
+    #[test]
+    fn test_non_boolean_index() {
+        // With index 2 the swap gate alone is satisfiable (l = 2b - a, r = 2a - b), so the digit gate must catch it
+        assert!(run(3, 4, 2, 5, 2).verify().is_err());
+    }
 }
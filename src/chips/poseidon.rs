@@ -3,9 +3,10 @@ An easy-to-use implementation of the Poseidon Hash in the form of a Halo2 Chip.
 is already implemented in halo2_gadgets, there is no wrapper chip that makes it easy to use in other circuits.
 */
 
+use ff::PrimeField;
 use halo2_gadgets::poseidon::{
     primitives::{ConstantLength, Spec},
-    Hash, Pow5Chip, Pow5Config,
+    Hash, PoseidonInstructions, Pow5Chip, Pow5Config,
 };
 use halo2_proofs::{
     circuit::{AssignedCell, Layouter, Value},
@@ -14,49 +15,82 @@ use halo2_proofs::{
         ConstraintSystem, Error, Instance,
     },
 };
-use halo2curves::pasta::{Fp};
 use std::marker::PhantomData;
 
+use super::arithmetic::{ArithmeticChip, ArithmeticConfig};
+
 #[derive(Debug, Clone)]
 
-pub struct PoseidonConfig<const WIDTH: usize, const RATE: usize, const L: usize> {
+pub struct PoseidonConfig<F: PrimeField, const WIDTH: usize, const RATE: usize, const L: usize> {
     inputs: Vec<Column<Advice>>,
-    instance: Column<Instance>,
-    pow5_config: Pow5Config<Fp, WIDTH, RATE>,
+    instance: Option<Column<Instance>>,
+    pow5_config: Pow5Config<F, WIDTH, RATE>,
+    arithmetic_config: Option<ArithmeticConfig>,
+}
+
+impl<F: PrimeField, const WIDTH: usize, const RATE: usize, const L: usize>
+    PoseidonConfig<F, WIDTH, RATE, L>
+{
+    /// The same configuration, for hashing messages of length `M` instead. The columns and gates do not depend on the
+    /// message length, so a circuit can configure the chip once and hash messages of several lengths with it.
+    pub fn with_length<const M: usize>(&self) -> PoseidonConfig<F, WIDTH, RATE, M> {
+        PoseidonConfig {
+            inputs: self.inputs.clone(),
+            instance: self.instance,
+            pow5_config: self.pow5_config.clone(),
+            arithmetic_config: self.arithmetic_config.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 
 pub struct PoseidonChip<
-    S: Spec<Fp, WIDTH, RATE>,
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
     const WIDTH: usize,
     const RATE: usize,
     const L: usize,
 > {
-    config: PoseidonConfig<WIDTH, RATE, L>,
+    config: PoseidonConfig<F, WIDTH, RATE, L>,
     _marker: PhantomData<S>,
 }
 
-impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: usize>
-    PoseidonChip<S, WIDTH, RATE, L>
+impl<
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE>,
+        const WIDTH: usize,
+        const RATE: usize,
+        const L: usize,
+    > PoseidonChip<F, S, WIDTH, RATE, L>
 {
-    pub fn construct(config: PoseidonConfig<WIDTH, RATE, L>) -> Self {
+    pub fn construct(config: PoseidonConfig<F, WIDTH, RATE, L>) -> Self {
         Self {
             config,
             _marker: PhantomData,
         }
     }
 
-    pub fn configure(meta: &mut ConstraintSystem<Fp>) -> PoseidonConfig<WIDTH, RATE, L> {
+    /// Configure the chip. `instance` is the column `expose_public` constrains against: circuits that embed the chip
+    /// and expose their public inputs through their own instance column can pass it here, or `None` if the hash
+    /// output is never exposed directly.
+    ///
+    /// This only configures the Poseidon permutation: circuits that hash messages of variable length or use a sponge
+    /// configure the chip with `configure_variable_length` instead.
+    pub fn configure(
+        meta: &mut ConstraintSystem<F>,
+        instance: Option<Column<Instance>>,
+    ) -> PoseidonConfig<F, WIDTH, RATE, L> {
         let state = (0..WIDTH).map(|_| meta.advice_column()).collect::<Vec<_>>();
         let partial_sbox = meta.advice_column();
         let rc_a = (0..WIDTH).map(|_| meta.fixed_column()).collect::<Vec<_>>();
         let rc_b = (0..WIDTH).map(|_| meta.fixed_column()).collect::<Vec<_>>();
-        let instance = meta.instance_column();
         for i in 0..WIDTH {
             meta.enable_equality(state[i]);
         }
-        meta.enable_equality(instance);
+        if let Some(instance) = instance {
+            meta.enable_equality(instance);
+        }
         meta.enable_constant(rc_b[0]);
 
         let pow5_config = Pow5Chip::configure::<S>(
@@ -71,29 +105,49 @@ impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: u
             inputs: state.clone().try_into().unwrap(),
             instance,
             pow5_config: pow5_config,
+            arithmetic_config: None,
         }
     }
 
+    /// Same as `configure`, along with the gates `hash_variable_length` and `sponge` need. They live on the first
+    /// three state columns, so the width must be at least 3.
+    pub fn configure_variable_length(
+        meta: &mut ConstraintSystem<F>,
+        instance: Option<Column<Instance>>,
+    ) -> PoseidonConfig<F, WIDTH, RATE, L> {
+        assert!(
+            WIDTH >= 3,
+            "variable-length hashing needs a width of at least 3"
+        );
+        let mut config = Self::configure(meta, instance);
+        let state = &config.inputs;
+        config.arithmetic_config = Some(ArithmeticChip::configure(
+            meta,
+            [state[0], state[1], state[2]],
+        ));
+        config
+    }
+
     pub fn load_private_inputs(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        inputs: [Value<Fp>; L],
-    ) -> Result<[AssignedCell<Fp, Fp>; L], Error> {
+        mut layouter: impl Layouter<F>,
+        inputs: [Value<F>; L],
+    ) -> Result<[AssignedCell<F, F>; L], Error> {
         layouter.assign_region(
             || "load private inputs",
-            |mut region| -> Result<[AssignedCell<Fp, Fp>; L], Error> {
+            |mut region| -> Result<[AssignedCell<F, F>; L], Error> {
                 let result = inputs
                     .iter()
                     .enumerate()
                     .map(|(i, x)| {
                         region.assign_advice(
                             || "private input",
-                            self.config.inputs[i],
-                            0,
+                            self.config.inputs[i % WIDTH],
+                            i / WIDTH,
                             || x.to_owned(),
                         )
                     })
-                    .collect::<Result<Vec<AssignedCell<Fp, Fp>>, Error>>();
+                    .collect::<Result<Vec<AssignedCell<F, F>>, Error>>();
                 Ok(result?.try_into().unwrap())
             },
         )
@@ -101,22 +155,23 @@ impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: u
 
     pub fn expose_public(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        cell: &AssignedCell<Fp, Fp>,
+        mut layouter: impl Layouter<F>,
+        cell: &AssignedCell<F, F>,
         row: usize,
     ) -> Result<(), Error> {
-        layouter.constrain_instance(cell.cell(), self.config.instance, row)
+        let instance = self.config.instance.ok_or(Error::Synthesis)?;
+        layouter.constrain_instance(cell.cell(), instance, row)
     }
 
     pub fn hash(
         &self,
-        mut layouter: impl Layouter<Fp>,
-        words: &[AssignedCell<Fp, Fp>; L],
-    ) -> Result<AssignedCell<Fp, Fp>, Error> {
+        mut layouter: impl Layouter<F>,
+        words: &[AssignedCell<F, F>; L],
+    ) -> Result<AssignedCell<F, F>, Error> {
         let pow5_chip = Pow5Chip::construct(self.config.pow5_config.clone());
         let word_cells = layouter.assign_region(
             || "load words",
-            |mut region| -> Result<[AssignedCell<Fp, Fp>; L], Error> {
+            |mut region| -> Result<[AssignedCell<F, F>; L], Error> {
                 let result = words
                     .iter()
                     .enumerate()
@@ -124,11 +179,11 @@ impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: u
                         word.copy_advice(
                             || format!("word {}", i),
                             &mut region,
-                            self.config.inputs[i],
-                            0,
+                            self.config.inputs[i % WIDTH],
+                            i / WIDTH,
                         )
                     })
-                    .collect::<Result<Vec<AssignedCell<Fp, Fp>>, Error>>();
+                    .collect::<Result<Vec<AssignedCell<F, F>>, Error>>();
                 Ok(result?.try_into().unwrap())
             },
         )?;
@@ -139,4 +194,249 @@ impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: u
         )?;
         hasher.hash(layouter.namespace(|| "hash"), word_cells)
     }
+
+    fn load_constant(
+        &self,
+        mut layouter: impl Layouter<F>,
+        constant: F,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        layouter.assign_region(
+            || "load constant",
+            |mut region| {
+                region.assign_advice_from_constant(
+                    || "constant",
+                    self.config.inputs[0],
+                    0,
+                    constant,
+                )
+            },
+        )
+    }
+
+    /// Hash the first `length` of `words`, where `length` is a witness of at most `L`. The digest is the one of
+    /// `ConstantLength<length>`, see `utils::poseidon::hash_variable_length`.
+    ///
+    /// The circuit always runs the permutations of a message of `L` words. Every word is multiplied by a bit telling
+    /// whether it is part of the message, so the words past `length` are absorbed as the zeros they are padded with.
+    /// The bits are constrained to be a run of ones followed by zeros that sums to `length`, which also bounds it by
+    /// `L`. The capacity starts at $2^{64} \cdot$ `length`, and the digest is taken after the last permutation that
+    /// absorbed part of the message.
+    pub fn hash_variable_length(
+        &self,
+        mut layouter: impl Layouter<F>,
+        words: &[AssignedCell<F, F>; L],
+        length: &AssignedCell<F, F>,
+    ) -> Result<AssignedCell<F, F>, Error> {
+        let arithmetic_chip = self.arithmetic_chip()?;
+        let pow5_chip = Pow5Chip::construct(self.config.pow5_config.clone());
+        let zero = self.load_constant(layouter.namespace(|| "zero"), F::ZERO)?;
+        let one = self.load_constant(layouter.namespace(|| "one"), F::ONE)?;
+
+        // The number of words the witness stands for. An out of range length makes every word part of the message,
+        // which the sum below rejects.
+        let length_value = length.value().map(|length| {
+            (0..=L)
+                .position(|i| F::from(i as u64) == *length)
+                .unwrap_or(L)
+        });
+
+        // Mask the words, keeping the bit of every word to pick the digest
+        let mut bits = vec![];
+        let mut masked_words = vec![];
+        let mut previous_bit = one.clone();
+        let mut sum = zero.clone();
+        for (i, word) in words.iter().enumerate() {
+            let bit = layouter.assign_region(
+                || format!("load bit {}", i),
+                |mut region| {
+                    region.assign_advice(
+                        || "bit",
+                        self.config.inputs[0],
+                        0,
+                        || length_value.map(|length| F::from((i < length) as u64)),
+                    )
+                },
+            )?;
+            arithmetic_chip.assert_bool(layouter.namespace(|| format!("bool {}", i)), &bit)?;
+            // A word can only be part of the message if the previous one is
+            let both = arithmetic_chip.mul_add(
+                layouter.namespace(|| format!("run {}", i)),
+                &zero,
+                &bit,
+                &previous_bit,
+            )?;
+            layouter.assign_region(
+                || format!("constrain run {}", i),
+                |mut region| region.constrain_equal(both.cell(), bit.cell()),
+            )?;
+            sum = arithmetic_chip.mul_add(
+                layouter.namespace(|| format!("sum {}", i)),
+                &sum,
+                &bit,
+                &one,
+            )?;
+            masked_words.push(arithmetic_chip.mul_add(
+                layouter.namespace(|| format!("mask {}", i)),
+                &zero,
+                word,
+                &bit,
+            )?);
+            bits.push(bit.clone());
+            previous_bit = bit;
+        }
+        layouter.assign_region(
+            || "constrain length",
+            |mut region| region.constrain_equal(sum.cell(), length.cell()),
+        )?;
+
+        let tag = self.load_constant(layouter.namespace(|| "tag"), F::from_u128(1 << 64))?;
+        let capacity =
+            arithmetic_chip.mul_add(layouter.namespace(|| "capacity"), &zero, length, &tag)?;
+
+        let chunks = masked_words.chunks(RATE).count().max(1);
+        let mut state = vec![zero.clone(); WIDTH];
+        state[RATE] = capacity;
+        let mut digest: Option<AssignedCell<F, F>> = None;
+        for chunk in 0..chunks {
+            for r in 0..RATE {
+                if let Some(word) = masked_words.get(chunk * RATE + r) {
+                    state[r] = arithmetic_chip.mul_add(
+                        layouter.namespace(|| format!("absorb {} {}", chunk, r)),
+                        &state[r],
+                        word,
+                        &one,
+                    )?;
+                }
+            }
+            let initial_state: [_; WIDTH] = std::array::from_fn(|i| state[i].clone().into());
+            let output_state = PoseidonInstructions::<F, S, WIDTH, RATE>::permute(
+                &pow5_chip,
+                &mut layouter.namespace(|| format!("permute {}", chunk)),
+                &initial_state,
+            )?;
+            state = output_state
+                .into_iter()
+                .map(|word| word.into())
+                .collect::<Vec<AssignedCell<F, F>>>();
+
+            // The chunk is part of the message if its first word is
+            digest = Some(match digest {
+                None => state[0].clone(),
+                Some(digest) => arithmetic_chip.select(
+                    layouter.namespace(|| format!("digest {}", chunk)),
+                    &bits[chunk * RATE],
+                    &state[0],
+                    &digest,
+                )?,
+            });
+        }
+        Ok(digest.unwrap())
+    }
+
+    /// Start a duplex sponge over the permutation of the chip, with `domain` as the initial capacity element. Sponges
+    /// started with different domains give unrelated outputs for the same inputs. Like `hash_variable_length`, this
+    /// needs the chip to be configured with `configure_variable_length`.
+    pub fn sponge(
+        &self,
+        mut layouter: impl Layouter<F>,
+        domain: F,
+    ) -> Result<PoseidonSponge<F, S, WIDTH, RATE>, Error> {
+        let arithmetic_chip = self.arithmetic_chip()?;
+        let zero = self.load_constant(layouter.namespace(|| "zero"), F::ZERO)?;
+        let mut state = vec![zero; WIDTH];
+        state[RATE] = self.load_constant(layouter.namespace(|| "domain"), domain)?;
+        Ok(PoseidonSponge {
+            pow5_config: self.config.pow5_config.clone(),
+            arithmetic_chip,
+            one: self.load_constant(layouter.namespace(|| "one"), F::ONE)?,
+            state,
+            absorbing: vec![],
+            output: vec![],
+            squeezing: false,
+            _marker: PhantomData,
+        })
+    }
+
+    // The chip for the gates of `configure_variable_length`, which a chip configured with `configure` does not have
+    fn arithmetic_chip(&self) -> Result<ArithmeticChip, Error> {
+        self.config
+            .arithmetic_config
+            .clone()
+            .map(ArithmeticChip::construct)
+            .ok_or(Error::Synthesis)
+    }
+}
+
+/// A duplex sponge in a circuit, the in-circuit counterpart of `utils::poseidon::Sponge`. It absorbs `RATE` cells per
+/// permutation, and squeezes `RATE` cells per permutation: the inputs absorbed since the last permutation are added to
+/// the state before the next one. A sponge can absorb again after squeezing, so circuits can derive several outputs
+/// from one transcript, e.g. for Fiat-Shamir challenges.
+#[derive(Debug, Clone)]
+pub struct PoseidonSponge<
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
+    const WIDTH: usize,
+    const RATE: usize,
+> {
+    pow5_config: Pow5Config<F, WIDTH, RATE>,
+    arithmetic_chip: ArithmeticChip,
+    one: AssignedCell<F, F>,
+    state: Vec<AssignedCell<F, F>>,
+    absorbing: Vec<AssignedCell<F, F>>,
+    output: Vec<AssignedCell<F, F>>,
+    squeezing: bool,
+    _marker: PhantomData<S>,
+}
+
+impl<F: PrimeField, S: Spec<F, WIDTH, RATE>, const WIDTH: usize, const RATE: usize>
+    PoseidonSponge<F, S, WIDTH, RATE>
+{
+    pub fn absorb(
+        &mut self,
+        mut layouter: impl Layouter<F>,
+        value: &AssignedCell<F, F>,
+    ) -> Result<(), Error> {
+        if self.squeezing {
+            self.squeezing = false;
+            self.output.clear();
+        }
+        if self.absorbing.len() == RATE {
+            self.permute(layouter.namespace(|| "permute"))?;
+        }
+        self.absorbing.push(value.clone());
+        Ok(())
+    }
+
+    pub fn squeeze(&mut self, mut layouter: impl Layouter<F>) -> Result<AssignedCell<F, F>, Error> {
+        if !self.squeezing || self.output.is_empty() {
+            self.permute(layouter.namespace(|| "permute"))?;
+            self.output = self.state[..RATE].to_vec();
+            self.squeezing = true;
+        }
+        Ok(self.output.remove(0))
+    }
+
+    // Add the pending inputs to the rate and permute the state
+    fn permute(&mut self, mut layouter: impl Layouter<F>) -> Result<(), Error> {
+        for (i, value) in self.absorbing.drain(..).enumerate() {
+            self.state[i] = self.arithmetic_chip.mul_add(
+                layouter.namespace(|| format!("absorb {}", i)),
+                &self.state[i],
+                &value,
+                &self.one,
+            )?;
+        }
+        let pow5_chip = Pow5Chip::construct(self.pow5_config.clone());
+        let initial_state: [_; WIDTH] = std::array::from_fn(|i| self.state[i].clone().into());
+        let output_state = PoseidonInstructions::<F, S, WIDTH, RATE>::permute(
+            &pow5_chip,
+            &mut layouter.namespace(|| "permutation"),
+            &initial_state,
+        )?;
+        self.state = output_state
+            .into_iter()
+            .map(|word| word.into())
+            .collect::<Vec<AssignedCell<F, F>>>();
+        Ok(())
+    }
 }
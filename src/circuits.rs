@@ -1,2 +1,11 @@
+pub mod batch_append;
+pub mod credential;
+pub mod indexed_merkle;
 pub mod merkle;
+pub mod merkle_multiproof;
+pub mod merkle_update;
 pub mod poseidon;
+pub mod rln;
+pub mod semaphore;
+pub mod sparse_merkle;
+pub mod variable_depth_merkle;
@@ -0,0 +1,231 @@
+/*
+Proves that a batch of leaves was appended to a merkle tree: the leaves fill an empty, aligned subtree of the tree,
+which moves from `old_root` to `new_root`. One such proof replaces a proof per appended leaf.
+*/
+
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::trees::merkle::empty_root;
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+/// Appends `N` leaves, `N` being a power of two, at positions `start_index..start_index + N`. The leaves are hashed
+/// into the root of a subtree of height `log2(N)`, which replaces an empty subtree: `start_index` has to be a multiple
+/// of `N` and both roots are computed from the same siblings above the subtree. The batch itself is committed to with
+/// `Poseidon(leaves)`, so that the verifier knows which leaves were appended.
+///
+/// The public inputs are `[old_root, new_root, start_index, commitment]`.
+#[derive(Clone)]
+pub struct BatchAppendCircuit<const N: usize> {
+    pub start_index: Value<Fp>,
+    pub leaves: [Value<Fp>; N],
+    pub elements: Vec<Value<Fp>>,
+}
+
+impl<const N: usize> BatchAppendCircuit<N> {
+    const HEIGHT: usize = N.trailing_zeros() as usize;
+}
+
+impl<const N: usize> Default for BatchAppendCircuit<N> {
+    fn default() -> Self {
+        Self {
+            start_index: Value::unknown(),
+            leaves: [Value::unknown(); N],
+            elements: vec![],
+        }
+    }
+}
+
+impl<const N: usize> Circuit<Fp> for BatchAppendCircuit<N> {
+    type Config = MerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        assert!(
+            N.is_power_of_two(),
+            "batches of {} leaves do not form a subtree",
+            N
+        );
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config.clone());
+        let hash_two_chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(
+            config.poseidon_config.clone(),
+        );
+        let commitment_chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, N>::construct(
+            config.poseidon_config.with_length(),
+        );
+        let depth = Self::HEIGHT + self.elements.len();
+
+        // The position of the subtree: the low bits of the start index are zero, the high ones are the path directions
+        let start_index =
+            chip.load_private(layouter.namespace(|| "load start index"), self.start_index)?;
+        chip.expose_public(layouter.namespace(|| "public start index"), &start_index, 2)?;
+        let bits = chip.decompose(layouter.namespace(|| "decompose"), &start_index, depth)?;
+        layouter.assign_region(
+            || "aligned start index",
+            |mut region| {
+                for bit in &bits[..Self::HEIGHT] {
+                    region.constrain_constant(bit.cell(), Fp::zero())?;
+                }
+                Ok(())
+            },
+        )?;
+        let elements = self
+            .elements
+            .iter()
+            .enumerate()
+            .map(|(i, element)| {
+                chip.load_private(
+                    layouter.namespace(|| format!("load element {}", i)),
+                    *element,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The subtree was empty before the append
+        let empty = chip.load_constant(
+            layouter.namespace(|| "empty subtree"),
+            empty_root(Self::HEIGHT),
+        )?;
+        let old_root = chip.merkle_prove_with_cells(
+            layouter.namespace(|| "old root"),
+            &empty,
+            &elements,
+            &bits[Self::HEIGHT..],
+        )?;
+        chip.expose_public(layouter.namespace(|| "public old root"), &old_root, 0)?;
+
+        // And holds the batch after it
+        let leaves = self
+            .leaves
+            .iter()
+            .enumerate()
+            .map(|(i, leaf)| {
+                chip.load_private(layouter.namespace(|| format!("load leaf {}", i)), *leaf)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut layer = leaves.clone();
+        for height in 0..Self::HEIGHT {
+            layer = layer
+                .chunks(2)
+                .enumerate()
+                .map(|(i, pair)| {
+                    hash_two_chip.hash(
+                        layouter.namespace(|| format!("subtree node {} {}", height + 1, i)),
+                        &[pair[0].clone(), pair[1].clone()],
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        let new_root = chip.merkle_prove_with_cells(
+            layouter.namespace(|| "new root"),
+            &layer[0],
+            &elements,
+            &bits[Self::HEIGHT..],
+        )?;
+        chip.expose_public(layouter.namespace(|| "public new root"), &new_root, 1)?;
+
+        let commitment = commitment_chip.hash(
+            layouter.namespace(|| "batch commitment"),
+            &leaves.try_into().unwrap(),
+        )?;
+        chip.expose_public(layouter.namespace(|| "public commitment"), &commitment, 3)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::merkle::{BatchAppend, MerkleTree};
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    const K: u32 = 11;
+
+    fn batch() -> BatchAppend<4> {
+        let leaves = (0..8).map(|_| Fp::random(OsRng)).collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(10, leaves[..4].to_vec());
+        tree.append_batch([leaves[4], leaves[5], leaves[6], leaves[7]])
+    }
+
+    #[test]
+    fn test_batch_append() {
+        let batch = batch();
+        let public_input = batch.public_inputs();
+        let circuit = BatchAppendCircuit::from(batch);
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+    }
+
+    #[test]
+    fn test_wrong_public_inputs() {
+        let batch = batch();
+        let circuit = BatchAppendCircuit::from(batch.clone());
+
+        // Every public input is bound: roots, position and batch commitment
+        for i in 0..4 {
+            let mut public_input = batch.public_inputs();
+            public_input[i] += Fp::one();
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    // Appending at 4 would overwrite the leaf already set there
+    #[test]
+    fn test_occupied_subtree() {
+        let leaves = (0..9).map(|_| Fp::random(OsRng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(10, leaves[..5].to_vec());
+        let mut appended = tree.clone();
+        for (i, leaf) in leaves[5..].iter().enumerate() {
+            appended.set(4 + i, *leaf);
+        }
+
+        let batch = BatchAppend {
+            start_index: 4,
+            leaves: [leaves[5], leaves[6], leaves[7], leaves[8]],
+            elements: tree.path(4).elements[2..].to_vec(),
+        };
+        let mut public_input = batch.public_inputs();
+        public_input[0] = tree.root();
+        assert_eq!(public_input[1], appended.root());
+
+        let circuit = BatchAppendCircuit::from(batch);
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_unaligned_start_index() {
+        let batch = batch();
+        let mut public_input = batch.public_inputs();
+        public_input[2] = Fp::from(batch.start_index + 2);
+        let mut circuit = BatchAppendCircuit::from(batch);
+        circuit.start_index = Value::known(public_input[2]);
+
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use ff::Field;
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+use rand_core::RngCore;
+
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::trees::merkle::{hash_nodes, MerklePath};
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+/// The leaf registering a credential in the tree
+pub fn credential_leaf(secret: Fp, trapdoor: Fp) -> Fp {
+    hash_nodes(secret, trapdoor)
+}
+
+/// The nullifier a credential reveals when used in `scope`
+pub fn scoped_nullifier(secret: Fp, scope: Fp) -> Fp {
+    hash_nodes(secret, scope)
+}
+
+/// An anonymous credential. Its leaf commits to the secret with a random trapdoor, so that a verifier picking a scope
+/// cannot make the nullifier coincide with the public leaf.
+#[derive(Debug, Clone, Copy)]
+pub struct Credential {
+    pub secret: Fp,
+    pub trapdoor: Fp,
+}
+
+impl Credential {
+    pub fn random(mut rng: impl RngCore) -> Self {
+        Self {
+            secret: Fp::random(&mut rng),
+            trapdoor: Fp::random(&mut rng),
+        }
+    }
+
+    pub fn leaf(&self) -> Fp {
+        credential_leaf(self.secret, self.trapdoor)
+    }
+
+    pub fn nullifier(&self, scope: Fp) -> Fp {
+        scoped_nullifier(self.secret, scope)
+    }
+
+    /// The circuit showing that this credential sits at the end of `path` and produces its nullifier for `scope`
+    pub fn circuit(&self, path: &MerklePath, scope: Fp) -> CredentialCircuit {
+        CredentialCircuit {
+            secret: Value::known(self.secret),
+            trapdoor: Value::known(self.trapdoor),
+            elements: path.elements.iter().map(|x| Value::known(*x)).collect(),
+            indices: path
+                .indices
+                .iter()
+                .map(|x| Value::known(Fp::from(*x)))
+                .collect(),
+            scope: Value::known(scope),
+        }
+    }
+}
+
+/// Proves ownership of a credential in a tree without revealing which one, and outputs its nullifier for a public
+/// scope. A credential always yields the same nullifier within a scope, so double use is detectable, while nullifiers
+/// from different scopes cannot be linked to each other.
+///
+/// The public inputs are `[root, scope, nullifier]`.
+#[derive(Clone, Default)]
+pub struct CredentialCircuit {
+    pub secret: Value<Fp>,
+    pub trapdoor: Value<Fp>,
+    pub elements: Vec<Value<Fp>>,
+    pub indices: Vec<Value<Fp>>,
+    pub scope: Value<Fp>,
+}
+
+impl Circuit<Fp> for CredentialCircuit {
+    type Config = MerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            indices: vec![Value::unknown(); self.indices.len()],
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config.clone());
+        let poseidon_chip =
+            PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(config.poseidon_config);
+
+        let secret = chip.load_private(layouter.namespace(|| "load secret"), self.secret)?;
+        let trapdoor = chip.load_private(layouter.namespace(|| "load trapdoor"), self.trapdoor)?;
+        let leaf = poseidon_chip.hash(
+            layouter.namespace(|| "credential leaf"),
+            &[secret.clone(), trapdoor],
+        )?;
+        let digest = chip.merkle_prove(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf,
+            &self.elements,
+            &self.indices,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, 0)?;
+
+        let scope = chip.load_private(layouter.namespace(|| "load scope"), self.scope)?;
+        chip.expose_public(layouter.namespace(|| "public scope"), &scope, 1)?;
+        let nullifier =
+            poseidon_chip.hash(layouter.namespace(|| "nullifier"), &[secret, scope])?;
+        chip.expose_public(layouter.namespace(|| "public nullifier"), &nullifier, 2)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::merkle::MerkleTree;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    const DEPTH: usize = 10;
+
+    fn credentials() -> (Vec<Credential>, MerkleTree) {
+        let credentials = (0..8).map(|_| Credential::random(OsRng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(DEPTH, credentials.iter().map(Credential::leaf).collect());
+        (credentials, tree)
+    }
+
+    #[test]
+    fn test_credential() {
+        let (credentials, tree) = credentials();
+        let scope = Fp::from(2024);
+        let credential = credentials[5];
+
+        let circuit = credential.circuit(&tree.path(5), scope);
+        let public_input = vec![tree.root(), scope, credential.nullifier(scope)];
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+
+        // Any other nullifier is rejected
+        let public_input = vec![tree.root(), scope, credential.nullifier(Fp::from(2025))];
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_nullifiers() {
+        let (credentials, _) = credentials();
+        let scopes = [Fp::from(1), Fp::from(2)];
+
+        // Stable within a scope, different across scopes and credentials
+        assert_eq!(
+            credentials[0].nullifier(scopes[0]),
+            scoped_nullifier(credentials[0].secret, scopes[0])
+        );
+        assert_ne!(
+            credentials[0].nullifier(scopes[0]),
+            credentials[0].nullifier(scopes[1])
+        );
+        assert_ne!(
+            credentials[0].nullifier(scopes[0]),
+            credentials[1].nullifier(scopes[0])
+        );
+    }
+
+    #[test]
+    fn test_unregistered_credential() {
+        let (_, tree) = credentials();
+        let scope = Fp::from(2024);
+        let credential = Credential::random(OsRng);
+
+        let circuit = credential.circuit(&tree.path(5), scope);
+        let public_input = vec![tree.root(), scope, credential.nullifier(scope)];
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Knowing someone else's trapdoor is not enough to produce their nullifier
+    #[test]
+    fn test_wrong_secret() {
+        let (credentials, tree) = credentials();
+        let scope = Fp::from(2024);
+        let forged = Credential {
+            secret: Fp::random(OsRng),
+            ..credentials[3]
+        };
+
+        let circuit = forged.circuit(&tree.path(3), scope);
+        let public_input = vec![tree.root(), scope, forged.nullifier(scope)];
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
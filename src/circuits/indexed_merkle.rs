@@ -0,0 +1,212 @@
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+
+use crate::chips::compare::{ComparisonChip, ComparisonConfig};
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+#[derive(Debug, Clone)]
+pub struct IndexedMerkleTreeConfig {
+    pub merkle_config: MerkleTreeConfig,
+    pub comparison_config: ComparisonConfig,
+}
+
+/// Proves that a public `value` is not in an indexed merkle tree with a public root. The prover opens the low leaf,
+/// the leaf holding the largest value below `value`, and shows that `value` lies strictly between the low leaf and its
+/// successor. A successor of zero means that the low leaf holds the largest value of the tree. Values are ordered as
+/// the integers in [0, p) they stand for, so the tree can hold any field element, e.g. Poseidon nullifiers.
+///
+/// The public inputs are `[value, root]`.
+#[derive(Clone, Default)]
+pub struct IndexedMerkleTreeCircuit {
+    pub value: Value<Fp>,
+    pub low_value: Value<Fp>,
+    pub low_next_index: Value<Fp>,
+    pub low_next_value: Value<Fp>,
+    pub elements: Vec<Value<Fp>>,
+    pub indices: Vec<Value<Fp>>,
+}
+
+impl Circuit<Fp> for IndexedMerkleTreeCircuit {
+    type Config = IndexedMerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            indices: vec![Value::unknown(); self.indices.len()],
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        IndexedMerkleTreeConfig {
+            merkle_config: MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance),
+            comparison_config: ComparisonChip::configure(meta, [col_a, col_b, col_c]),
+        }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let merkle_chip = MerkleTreeChip::construct(config.merkle_config.clone());
+        let comparison_chip = ComparisonChip::construct(config.comparison_config);
+        let poseidon_chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(
+            config.merkle_config.poseidon_config,
+        );
+
+        let value = merkle_chip.load_private(layouter.namespace(|| "load value"), self.value)?;
+        merkle_chip.expose_public(layouter.namespace(|| "public value"), &value, 0)?;
+
+        // The low leaf has to be in the tree
+        let low_value =
+            merkle_chip.load_private(layouter.namespace(|| "load low value"), self.low_value)?;
+        let low_next_index = merkle_chip
+            .load_private(layouter.namespace(|| "load low next index"), self.low_next_index)?;
+        let low_next_value = merkle_chip
+            .load_private(layouter.namespace(|| "load low next value"), self.low_next_value)?;
+        let partial_leaf = poseidon_chip.hash(
+            layouter.namespace(|| "leaf hash value"),
+            &[low_value.clone(), low_next_index],
+        )?;
+        let leaf = poseidon_chip.hash(
+            layouter.namespace(|| "leaf hash next value"),
+            &[partial_leaf, low_next_value.clone()],
+        )?;
+        let digest = merkle_chip.merkle_prove(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf,
+            &self.elements,
+            &self.indices,
+        )?;
+        merkle_chip.expose_public(layouter.namespace(|| "public root"), &digest, 1)?;
+
+        // low value < value < successor, on the canonical limbs of the values
+        let low_limbs =
+            comparison_chip.decompose(layouter.namespace(|| "split low value"), &low_value)?;
+        let value_limbs =
+            comparison_chip.decompose(layouter.namespace(|| "split value"), &value)?;
+        let low_next_limbs = comparison_chip.decompose(
+            layouter.namespace(|| "split low next value"),
+            &low_next_value,
+        )?;
+        comparison_chip.less_than(
+            layouter.namespace(|| "low value < value"),
+            &low_limbs,
+            &value_limbs,
+        )?;
+        let upper_bound = comparison_chip.or_max(
+            layouter.namespace(|| "upper bound"),
+            &low_next_value,
+            &low_next_limbs,
+        )?;
+        comparison_chip.less_than(
+            layouter.namespace(|| "value < upper bound"),
+            &value_limbs,
+            &upper_bound,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::indexed_merkle::IndexedMerkleTree;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    const K: u32 = 12;
+
+    fn tree() -> IndexedMerkleTree {
+        let mut tree = IndexedMerkleTree::new(16);
+        for value in [30u64, 10, 20, 1000] {
+            tree.insert(Fp::from(value));
+        }
+        tree
+    }
+
+    #[test]
+    fn test_non_membership() {
+        let tree = tree();
+        // Between two values, below all values and above all values
+        for value in [15u64, 5, 2000] {
+            let proof = tree.non_membership_proof(Fp::from(value)).unwrap();
+            let public_input = proof.public_inputs();
+            let circuit = IndexedMerkleTreeCircuit::from(proof);
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            prover.assert_satisfied();
+        }
+    }
+
+    #[test]
+    fn test_wrong_root() {
+        let tree = tree();
+        let proof = tree.non_membership_proof(Fp::from(15)).unwrap();
+        let circuit = IndexedMerkleTreeCircuit::from(proof);
+        let prover = MockProver::run(K, &circuit, vec![vec![Fp::from(15), Fp::from(1)]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Reusing the low leaf of a non-member to claim that one of its neighbours is not in the tree
+    #[test]
+    fn test_members() {
+        let tree = tree();
+        let proof = tree.non_membership_proof(Fp::from(15)).unwrap();
+        let root = proof.root();
+        assert!(tree.non_membership_proof(Fp::from(10)).is_none());
+
+        for value in [10u64, 20] {
+            let mut circuit = IndexedMerkleTreeCircuit::from(proof.clone());
+            circuit.value = Value::known(Fp::from(value));
+            let prover =
+                MockProver::run(K, &circuit, vec![vec![Fp::from(value), root]]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    // Values anywhere in the field, including the largest ones, are ordered as integers
+    #[test]
+    fn test_full_field() {
+        let mut rng = OsRng;
+        let mut tree = IndexedMerkleTree::new(16);
+        let largest = -Fp::from(2);
+        let values = [
+            largest,
+            Fp::from(2).pow_vartime([252]),
+            Fp::random(&mut rng),
+            Fp::random(&mut rng),
+        ];
+        for value in values {
+            tree.insert(value);
+        }
+
+        for value in [
+            -Fp::one(),
+            largest - Fp::one(),
+            Fp::from(2).pow_vartime([252]) + Fp::one(),
+            Fp::random(&mut rng),
+        ] {
+            let proof = tree.non_membership_proof(value).unwrap();
+            let public_input = proof.public_inputs();
+            let circuit = IndexedMerkleTreeCircuit::from(proof);
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            prover.assert_satisfied();
+        }
+
+        // The largest value cannot be claimed to be above itself
+        let proof = tree.non_membership_proof(-Fp::one()).unwrap();
+        let mut circuit = IndexedMerkleTreeCircuit::from(proof.clone());
+        circuit.value = Value::known(largest);
+        let prover = MockProver::run(K, &circuit, vec![vec![largest, proof.root()]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -1,41 +1,75 @@
 use halo2_gadgets::poseidon::{
-    primitives::{self as poseidon, ConstantLength},
+    primitives::{self as poseidon, ConstantLength, Spec},
 };
+use ff::PrimeField;
 use halo2_proofs::{circuit::*, plonk::*};
 use halo2curves::pasta::{Fp};
+use std::marker::PhantomData;
 
 use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
 
-use crate::chips::merkle::MerkleTreeChip;
+use crate::chips::merkle::{children, MerkleTreeChip};
 use crate::chips::merkle::MerkleTreeConfig;
 
-#[derive(Clone, Default)]
-pub struct MerkleTreeCircuit {
-    pub leaf: Value<Fp>,
-    pub elements: Vec<Value<Fp>>,
-    pub indices: Vec<Value<Fp>>,
+/// Proves that `leaf` is in the tree of a public root. The tree hashes `ARITY` children per node with the Poseidon `S`
+/// of `WIDTH` and `RATE` over `F`, and defaults to the binary tree over `P128Pow5T3` on Pasta. Every layer has an
+/// index, the position of the path among the children, and `ARITY - 1` consecutive `elements`.
+///
+/// The public inputs are `[leaf, root]`.
+#[derive(Clone)]
+pub struct MerkleTreeCircuit<
+    F = Fp,
+    S = OrchardNullifier,
+    const WIDTH: usize = 3,
+    const RATE: usize = 2,
+    const ARITY: usize = 2,
+> {
+    pub leaf: Value<F>,
+    pub elements: Vec<Value<F>>,
+    pub indices: Vec<Value<F>>,
+    pub _spec: PhantomData<S>,
 }
 
-impl Circuit<Fp> for MerkleTreeCircuit {
-    type Config = MerkleTreeConfig;
+impl<F, S, const WIDTH: usize, const RATE: usize, const ARITY: usize> Default
+    for MerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>
+{
+    fn default() -> Self {
+        Self {
+            leaf: Value::unknown(),
+            elements: vec![],
+            indices: vec![],
+            _spec: PhantomData,
+        }
+    }
+}
+
+impl<
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE> + Clone,
+        const WIDTH: usize,
+        const RATE: usize,
+        const ARITY: usize,
+    > Circuit<F> for MerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>
+{
+    type Config = MerkleTreeConfig<F, S, WIDTH, RATE, ARITY>;
     type FloorPlanner = SimpleFloorPlanner;
 
     fn without_witnesses(&self) -> Self {
         Self::default()
     }
 
-    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
-        let col_a = meta.advice_column();
-        let col_b = meta.advice_column();
-        let col_c = meta.advice_column();
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let advice = (0..=ARITY)
+            .map(|_| meta.advice_column())
+            .collect::<Vec<_>>();
         let instance = meta.instance_column();
-        MerkleTreeChip::configure(meta, [col_a, col_b, col_c], instance)
+        MerkleTreeChip::configure(meta, &advice, instance)
     }
 
     fn synthesize(
         &self,
         config: Self::Config,
-        mut layouter: impl Layouter<Fp>,
+        mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
         let chip = MerkleTreeChip::construct(config);
         let leaf_cell = chip.load_private(layouter.namespace(|| "load leaf"), self.leaf)?;
@@ -53,23 +87,292 @@ impl Circuit<Fp> for MerkleTreeCircuit {
     }
 }
 
-// Helper function for computing a merkle root given a leaf, directions and the siblings
-// elements correspond to siblings
-pub fn compute_merkle_root(leaf: &Fp, elements: &Vec<Fp>, indices: &Vec<u64>) -> Fp {
-    let k = elements.len();
-    let mut digest = leaf.clone();
-    let mut message: [Fp; 2];
-    for i in 0..k {
-        if indices[i] == 0 {
-            message = [digest, elements[i]];
-        } else {
-            message = [elements[i], digest];
+/// Same as `MerkleTreeCircuit`, but the leaf stays private so that the proof does not reveal which member produced it.
+/// The tree is the binary tree over `P128Pow5T3`, on either Pasta field. The only public input is the root, in row 0
+/// of the instance column.
+#[derive(Clone, Default)]
+pub struct PrivateMerkleTreeCircuit<F = Fp> {
+    pub leaf: Value<F>,
+    pub elements: Vec<Value<F>>,
+    pub indices: Vec<Value<F>>,
+}
+
+impl<F: PrimeField> Circuit<F> for PrivateMerkleTreeCircuit<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    type Config = MerkleTreeConfig<F>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::default()
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        <MerkleTreeCircuit<F>>::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config);
+        let leaf_cell = chip.load_private(layouter.namespace(|| "load leaf"), self.leaf)?;
+        let digest = chip.merkle_prove(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf_cell,
+            &self.elements,
+            &self.indices,
+        )?;
+        // Constraint digest to be placed in row 0 of the instance column
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, 0)?;
+        Ok(())
+    }
+}
+
+/// Proves that several leaves, each with its own path, belong to the same tree in a single proof. All the computed
+/// digests are constrained to one root cell.
+///
+/// The public inputs are the leaves in rows `0..n`, followed by the root in row `n`.
+#[derive(Clone, Default)]
+pub struct MultiMerkleTreeCircuit<F = Fp> {
+    pub leaves: Vec<Value<F>>,
+    pub elements: Vec<Vec<Value<F>>>,
+    pub indices: Vec<Vec<Value<F>>>,
+}
+
+impl<F: PrimeField> Circuit<F> for MultiMerkleTreeCircuit<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    type Config = MerkleTreeConfig<F>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            leaves: vec![Value::unknown(); self.leaves.len()],
+            elements: self
+                .elements
+                .iter()
+                .map(|elements| vec![Value::unknown(); elements.len()])
+                .collect(),
+            indices: self
+                .indices
+                .iter()
+                .map(|indices| vec![Value::unknown(); indices.len()])
+                .collect(),
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        <MerkleTreeCircuit<F>>::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        assert!(!self.leaves.is_empty(), "there has to be at least one leaf to prove");
+        let chip = MerkleTreeChip::construct(config);
+
+        let mut root: Option<AssignedCell<F, F>> = None;
+        for (i, leaf) in self.leaves.iter().enumerate() {
+            let leaf_cell =
+                chip.load_private(layouter.namespace(|| format!("load leaf {}", i)), *leaf)?;
+            chip.expose_public(
+                layouter.namespace(|| format!("public leaf {}", i)),
+                &leaf_cell,
+                i,
+            )?;
+            let digest = chip.merkle_prove(
+                layouter.namespace(|| format!("merkle_prove {}", i)),
+                &leaf_cell,
+                &self.elements[i],
+                &self.indices[i],
+            )?;
+            match &root {
+                Some(root) => chip.constrain_equal(
+                    layouter.namespace(|| format!("same root {}", i)),
+                    root,
+                    &digest,
+                )?,
+                None => root = Some(digest),
+            }
+        }
+        // Constraint the common root to be placed right after the leaves in the instance column
+        chip.expose_public(
+            layouter.namespace(|| "public root"),
+            &root.unwrap(),
+            self.leaves.len(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Same as `MerkleTreeCircuit`, but the path directions come from the position of the leaf, a single integer that is
+/// decomposed into `depth` digits in base `ARITY` (bits for binary trees) in-circuit. This also checks that the position
+/// is within the tree. If `public_position` is set, the position is exposed too, to prove that the leaf sits at a given
+/// position.
+///
+/// The public inputs are `[leaf, root]`, or `[leaf, root, position]` with `public_position`.
+#[derive(Clone)]
+pub struct PositionalMerkleTreeCircuit<
+    F = Fp,
+    S = OrchardNullifier,
+    const WIDTH: usize = 3,
+    const RATE: usize = 2,
+    const ARITY: usize = 2,
+> {
+    pub leaf: Value<F>,
+    pub elements: Vec<Value<F>>,
+    pub position: Value<F>,
+    pub public_position: bool,
+    pub _spec: PhantomData<S>,
+}
+
+impl<F, S, const WIDTH: usize, const RATE: usize, const ARITY: usize> Default
+    for PositionalMerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>
+{
+    fn default() -> Self {
+        Self {
+            leaf: Value::unknown(),
+            elements: vec![],
+            position: Value::unknown(),
+            public_position: false,
+            _spec: PhantomData,
+        }
+    }
+}
+
+impl<
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE> + Clone,
+        const WIDTH: usize,
+        const RATE: usize,
+        const ARITY: usize,
+    > Circuit<F> for PositionalMerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>
+{
+    type Config = MerkleTreeConfig<F, S, WIDTH, RATE, ARITY>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            public_position: self.public_position,
+            ..Self::default()
         }
+    }
 
-        digest =
-            poseidon::Hash::<_, OrchardNullifier, ConstantLength<2>, 3, 2>::init().hash(message);
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        MerkleTreeCircuit::<F, S, WIDTH, RATE, ARITY>::configure(meta)
     }
-    return digest;
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config);
+        let leaf_cell = chip.load_private(layouter.namespace(|| "load leaf"), self.leaf)?;
+        chip.expose_public(layouter.namespace(|| "public leaf"), &leaf_cell, 0)?;
+
+        let position = chip.load_private(layouter.namespace(|| "load position"), self.position)?;
+        if self.public_position {
+            chip.expose_public(layouter.namespace(|| "public position"), &position, 2)?;
+        }
+        let depth = self.elements.len() / (ARITY - 1);
+        let digits = chip.decompose(layouter.namespace(|| "decompose"), &position, depth)?;
+
+        let digest = chip.merkle_prove_with_bits(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf_cell,
+            &self.elements,
+            &digits,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, 1)?;
+        Ok(())
+    }
+}
+
+/// The indices of the layers of a path to the leaf at `position` in a tree of `depth` with `arity` children per node
+pub fn position_indices(position: u64, depth: usize, arity: usize) -> Vec<u64> {
+    let arity = arity as u64;
+    assert!(
+        (arity as u128)
+            .checked_pow(depth as u32)
+            .map_or(true, |leaves| leaves > position as u128),
+        "position {} out of range",
+        position
+    );
+    (0..depth)
+        .scan(position, |rest, _| {
+            let index = *rest % arity;
+            *rest /= arity;
+            Some(index)
+        })
+        .collect()
+}
+
+/// Same as `compute_merkle_root`, with the path directions given by the position of the leaf
+pub fn compute_merkle_root_at<F: PrimeField>(leaf: &F, elements: &[F], position: u64) -> F
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    compute_merkle_root_with::<_, OrchardNullifier, 3, 2, 2>(
+        leaf,
+        elements,
+        &position_indices(position, elements.len(), 2),
+    )
+}
+
+/// Hash the `ARITY` children of a node into the node, the way `MerkleTreeChip` does
+pub fn hash_children<
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
+    const WIDTH: usize,
+    const RATE: usize,
+    const ARITY: usize,
+>(
+    children: [F; ARITY],
+) -> F {
+    poseidon::Hash::<_, S, ConstantLength<ARITY>, WIDTH, RATE>::init().hash(children)
+}
+
+/// Same as `compute_merkle_root` for the tree of a `MerkleTreeCircuit<F, S, WIDTH, RATE, ARITY>`: every layer has
+/// `ARITY - 1` consecutive elements and an index going from 0 to `ARITY - 1`.
+pub fn compute_merkle_root_with<
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
+    const WIDTH: usize,
+    const RATE: usize,
+    const ARITY: usize,
+>(
+    leaf: &F,
+    elements: &[F],
+    indices: &[u64],
+) -> F {
+    assert_eq!(elements.len(), indices.len() * (ARITY - 1));
+    elements
+        .chunks(ARITY - 1)
+        .zip(indices)
+        .fold(*leaf, |digest, (elements, index)| {
+            hash_children::<F, S, WIDTH, RATE, ARITY>(children::<F, ARITY>(
+                digest,
+                elements,
+                F::from(*index),
+            ))
+        })
+}
+
+// Helper function for computing a merkle root given a leaf, directions and the siblings
+// elements correspond to siblings
+pub fn compute_merkle_root<F: PrimeField>(leaf: &F, elements: &Vec<F>, indices: &Vec<u64>) -> F
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    compute_merkle_root_with::<_, OrchardNullifier, 3, 2, 2>(leaf, elements, indices)
 }
 
 #[cfg(test)]
@@ -78,7 +381,9 @@ mod tests {
     use ff::Field;
     use halo2_proofs::dev::MockProver;
     use rand_core::OsRng;
-    use halo2curves::pasta::{vesta, EqAffine};
+    use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine};
+    use halo2curves::pasta::{pallas, vesta, EqAffine, Fq};
+    use crate::utils::p128pow5t5::P128Pow5T5;
 
     use halo2_proofs::{
         plonk::{
@@ -91,6 +396,11 @@ mod tests {
                 multiopen::ProverIPA,
                 strategy::SingleStrategy,
             },
+            kzg::{
+                commitment::{KZGCommitmentScheme, ParamsKZG},
+                multiopen::{ProverGWC, ProverSHPLONK, VerifierGWC, VerifierSHPLONK},
+                strategy::SingleStrategy as KZGSingleStrategy,
+            },
             VerificationStrategy,
         },
         transcript::{
@@ -119,17 +429,18 @@ mod tests {
             .map(|x| Value::known(Fp::from(x.to_owned())))
             .collect();
 
-        let circuit = MerkleTreeCircuit {
+        let circuit: MerkleTreeCircuit = MerkleTreeCircuit {
             leaf: leaf_fp,
             elements: elements_fp,
             indices: indices_fp,
+            _spec: PhantomData,
         };
 
         let correct_public_input = vec![Fp::from(leaf), Fp::from(digest)];
         let correct_prover = MockProver::run(
             10,
             &circuit,
-            vec![correct_public_input.clone(), correct_public_input.clone()],
+            vec![correct_public_input.clone()],
         )
         .unwrap();
         correct_prover.assert_satisfied();
@@ -138,7 +449,7 @@ mod tests {
         let wrong_prover = MockProver::run(
             10,
             &circuit,
-            vec![wrong_public_input.clone(), wrong_public_input.clone()],
+            vec![wrong_public_input.clone()],
         )
         .unwrap();
 
@@ -149,6 +460,222 @@ mod tests {
         };
     }
 
+    // The same circuits run over the base field of Vesta, the other half of the Pasta cycle
+    #[test]
+    fn test_fq() {
+        let mut rng = OsRng;
+        let leaf = Fq::random(&mut rng);
+        let elements = (0..10).map(|_| Fq::random(&mut rng)).collect::<Vec<_>>();
+        let indices = vec![1u64, 0, 0, 1, 1, 0, 1, 0, 0, 1];
+        let digest = compute_merkle_root(&leaf, &elements, &indices);
+
+        let circuit: MerkleTreeCircuit<Fq> = MerkleTreeCircuit {
+            leaf: Value::known(leaf),
+            elements: elements.iter().map(|x| Value::known(*x)).collect(),
+            indices: indices.iter().map(|x| Value::known(Fq::from(*x))).collect(),
+            _spec: PhantomData,
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![leaf, digest]]).unwrap();
+        prover.assert_satisfied();
+        let prover = MockProver::run(10, &circuit, vec![vec![leaf, digest + Fq::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+
+        let circuit = PrivateMerkleTreeCircuit {
+            leaf: circuit.leaf,
+            elements: circuit.elements,
+            indices: circuit.indices,
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![digest]]).unwrap();
+        prover.assert_satisfied();
+    }
+
+    // The leaf and the root share a single instance column
+    #[test]
+    fn test_instance_columns() {
+        let mut meta = ConstraintSystem::<Fp>::default();
+        <MerkleTreeCircuit>::configure(&mut meta);
+        assert_eq!(meta.num_instance_columns(), 1);
+    }
+
+    #[test]
+    fn test_private_leaf() {
+        let mut rng = OsRng;
+        let leaf_f = Fp::random(&mut rng);
+        let elements_f = (0..10).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let indices = vec![1u64, 0, 0, 1, 1, 0, 1, 0, 0, 1];
+
+        let digest = compute_merkle_root(&leaf_f, &elements_f, &indices);
+
+        let circuit = PrivateMerkleTreeCircuit {
+            leaf: Value::known(leaf_f),
+            elements: elements_f.iter().map(|x| Value::known(*x)).collect(),
+            indices: indices.iter().map(|x| Value::known(Fp::from(*x))).collect(),
+        };
+
+        // Only the root is public
+        let prover = MockProver::run(10, &circuit, vec![vec![digest]]).unwrap();
+        prover.assert_satisfied();
+
+        let prover = MockProver::run(10, &circuit, vec![vec![leaf_f]]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // A leaf that is not in the tree does not lead to the root
+        let circuit = PrivateMerkleTreeCircuit {
+            leaf: Value::known(leaf_f + Fp::one()),
+            ..circuit
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![digest]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_multiple_leaves() {
+        let mut rng = OsRng;
+        let leaves = (0..64).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = crate::trees::merkle::MerkleTree::new(8, leaves.clone());
+        let positions = [3, 17, 18, 60];
+
+        let circuit = MultiMerkleTreeCircuit::from(
+            positions.iter().map(|i| tree.path(*i)).collect::<Vec<_>>(),
+        );
+        let mut public_input = positions.iter().map(|i| leaves[*i]).collect::<Vec<_>>();
+        public_input.push(tree.root());
+        let prover = MockProver::run(11, &circuit, vec![public_input.clone()]).unwrap();
+        prover.assert_satisfied();
+
+        // A path into another tree cannot join, even if it is valid on its own
+        let other = crate::trees::merkle::MerkleTree::new(8, leaves[..32].to_vec());
+        let mut paths = positions.iter().map(|i| tree.path(*i)).collect::<Vec<_>>();
+        paths[1] = other.path(17);
+        let circuit = MultiMerkleTreeCircuit::from(paths);
+        let prover = MockProver::run(11, &circuit, vec![public_input.clone()]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // Every leaf is public
+        let mut wrong_public_input = public_input;
+        wrong_public_input[2] = leaves[19];
+        let circuit = MultiMerkleTreeCircuit::from(
+            positions.iter().map(|i| tree.path(*i)).collect::<Vec<_>>(),
+        );
+        let prover = MockProver::run(11, &circuit, vec![wrong_public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    type QuaternaryMerkleTreeCircuit = MerkleTreeCircuit<Fp, P128Pow5T5, 5, 4, 4>;
+
+    fn quaternary_circuit(depth: usize) -> (QuaternaryMerkleTreeCircuit, Vec<Fp>) {
+        let mut rng = OsRng;
+        let leaf = Fp::random(&mut rng);
+        let elements = (0..3 * depth).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let indices = (0..depth).map(|i| (i % 4) as u64).collect::<Vec<_>>();
+        let root = compute_merkle_root_with::<_, P128Pow5T5, 5, 4, 4>(&leaf, &elements, &indices);
+
+        let circuit = QuaternaryMerkleTreeCircuit {
+            leaf: Value::known(leaf),
+            elements: elements.into_iter().map(Value::known).collect(),
+            indices: indices.iter().map(|x| Value::known(Fp::from(*x))).collect(),
+            _spec: PhantomData,
+        };
+        (circuit, vec![leaf, root])
+    }
+
+    #[test]
+    fn test_quaternary_root() {
+        let leaves = (1..=16u64).map(Fp::from).collect::<Vec<_>>();
+        let nodes = leaves
+            .chunks(4)
+            .map(|children| hash_children::<_, P128Pow5T5, 5, 4, 4>(children.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let root = hash_children::<_, P128Pow5T5, 5, 4, 4>(nodes.clone().try_into().unwrap());
+
+        // Leaf 9 is the second child of the third node
+        let elements = vec![leaves[8], leaves[10], leaves[11], nodes[0], nodes[1], nodes[3]];
+        assert_eq!(
+            compute_merkle_root_with::<_, P128Pow5T5, 5, 4, 4>(&leaves[9], &elements, &[1, 2]),
+            root
+        );
+    }
+
+    #[test]
+    fn test_quaternary() {
+        let (circuit, public_input) = quaternary_circuit(8);
+        let prover = MockProver::run(10, &circuit, vec![public_input.clone()]).unwrap();
+        prover.assert_satisfied();
+
+        let wrong_public_input = vec![public_input[0], Fp::from(432058235)];
+        let prover = MockProver::run(10, &circuit, vec![wrong_public_input]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // Positions outside of 0..4 are rejected
+        let (mut circuit, public_input) = quaternary_circuit(8);
+        circuit.indices[3] = Value::known(Fp::from(4));
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_position() {
+        let mut rng = OsRng;
+        let leaf = Fp::random(&mut rng);
+        let elements = (0..10).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let position = 0b1001101101u64;
+        let digest = compute_merkle_root_at(&leaf, &elements, position);
+        assert_eq!(
+            digest,
+            compute_merkle_root(&leaf, &elements, &position_indices(position, 10, 2))
+        );
+
+        let circuit = |position: u64, public_position: bool| -> PositionalMerkleTreeCircuit {
+            PositionalMerkleTreeCircuit {
+                leaf: Value::known(leaf),
+                elements: elements.iter().map(|x| Value::known(*x)).collect(),
+                position: Value::known(Fp::from(position)),
+                public_position,
+                _spec: PhantomData,
+            }
+        };
+
+        // Private position
+        let prover =
+            MockProver::run(10, &circuit(position, false), vec![vec![leaf, digest]]).unwrap();
+        prover.assert_satisfied();
+        let prover =
+            MockProver::run(10, &circuit(position + 1, false), vec![vec![leaf, digest]]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // Public position
+        let public_input = vec![leaf, digest, Fp::from(position)];
+        let prover = MockProver::run(10, &circuit(position, true), vec![public_input]).unwrap();
+        prover.assert_satisfied();
+        let public_input = vec![leaf, digest, Fp::from(position + 1)];
+        let prover = MockProver::run(10, &circuit(position, true), vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+
+        // A position past the end of the tree has the same low bits, but does not fit in the path
+        let public_input = vec![leaf, digest, Fp::from(position + (1 << 10))];
+        let prover =
+            MockProver::run(10, &circuit(position + (1 << 10), true), vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_quaternary_position() {
+        let (circuit, public_input) = quaternary_circuit(6);
+        let position = (0..6).rev().fold(0u64, |acc, i| acc * 4 + (i % 4) as u64);
+        assert_eq!(position_indices(position, 6, 4), vec![0, 1, 2, 3, 0, 1]);
+
+        let circuit = PositionalMerkleTreeCircuit::<Fp, P128Pow5T5, 5, 4, 4> {
+            leaf: circuit.leaf,
+            elements: circuit.elements,
+            position: Value::known(Fp::from(position)),
+            public_position: true,
+            _spec: PhantomData,
+        };
+        let public_input = vec![public_input[0], public_input[1], Fp::from(position)];
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+    }
+
     // Same as `test()` but for a tree of depth 24
     #[test]
     fn test_big() {
@@ -169,17 +696,18 @@ mod tests {
             .map(|x| Value::known(Fp::from(x.to_owned())))
             .collect();
 
-        let circuit = MerkleTreeCircuit {
+        let circuit: MerkleTreeCircuit = MerkleTreeCircuit {
             leaf: leaf_fp,
             elements: elements_fp,
             indices: indices_fp,
+            _spec: PhantomData,
         };
 
         let correct_public_input = vec![leaf_f, Fp::from(digest)];
         let correct_prover = MockProver::run(
             10,
             &circuit,
-            vec![correct_public_input.clone(), correct_public_input.clone()],
+            vec![correct_public_input.clone()],
         )
         .unwrap();
         correct_prover.assert_satisfied();
@@ -188,7 +716,7 @@ mod tests {
         let wrong_prover = MockProver::run(
             10,
             &circuit,
-            vec![wrong_public_input.clone(), wrong_public_input.clone()],
+            vec![wrong_public_input.clone()],
         )
         .unwrap();
 
@@ -207,10 +735,11 @@ mod tests {
 
         // Initialization
         let params: ParamsIPA<vesta::Affine> = ParamsIPA::new(K);
-        let empty_circuit = MerkleTreeCircuit {
+        let empty_circuit: MerkleTreeCircuit = MerkleTreeCircuit {
             leaf: Value::unknown(),
             elements: vec![Value::unknown(); DEPTH],
             indices: vec![Value::unknown(); DEPTH],
+            _spec: PhantomData,
         };
         let vk = keygen_vk(&params, &empty_circuit).expect("keygen_vk should not fail");
         let pk = keygen_pk(&params, vk, &empty_circuit).expect("keygen_pk should not fail");
@@ -233,10 +762,11 @@ mod tests {
             .map(|x| Value::known(Fp::from(x.to_owned())))
             .collect();
 
-        let circuit = MerkleTreeCircuit {
+        let circuit: MerkleTreeCircuit = MerkleTreeCircuit {
             leaf: leaf_fp,
             elements: elements_fp,
             indices: indices_fp,
+            _spec: PhantomData,
         };
 
         let public_input = vec![leaf_f, digest];
@@ -244,7 +774,7 @@ mod tests {
         let prover = MockProver::run(
             K,
             &circuit,
-            vec![public_input.clone(), public_input.clone()],
+            vec![public_input.clone()],
         )
         .unwrap();
         prover.assert_satisfied();
@@ -255,7 +785,7 @@ mod tests {
             &params,
             &pk,
             &[circuit.clone()],
-            &[&[&public_input, &public_input]],
+            &[&[&public_input]],
             &mut rng,
             &mut transcript,
         )
@@ -269,7 +799,7 @@ mod tests {
             &params,
             pk.get_vk(),
             strategy,
-            &[&[&public_input, &public_input]],
+            &[&[&public_input]],
             &mut transcript,
         );
 
@@ -283,7 +813,7 @@ mod tests {
             &params,
             pk.get_vk(),
             strategy,
-            &[&[&wrong_public_input, &wrong_public_input]],
+            &[&[&wrong_public_input]],
             &mut transcript,
         );
 
@@ -292,4 +822,176 @@ mod tests {
             "Verification succeded when it should have failed"
         );
     }
+
+    // Same as `test_merkle_proving_and_verifying`, but over the base field of Vesta with the Pallas curve, the other
+    // side of the Pasta cycle
+    #[test]
+    fn test_fq_proving_and_verifying() {
+        const DEPTH: usize = 24;
+        const K: u32 = 10;
+
+        // Initialization
+        let params: ParamsIPA<pallas::Affine> = ParamsIPA::new(K);
+        let empty_circuit: MerkleTreeCircuit<Fq> = MerkleTreeCircuit {
+            leaf: Value::unknown(),
+            elements: vec![Value::unknown(); DEPTH],
+            indices: vec![Value::unknown(); DEPTH],
+            _spec: PhantomData,
+        };
+        let vk = keygen_vk(&params, &empty_circuit).expect("keygen_vk should not fail");
+        let pk = keygen_pk(&params, vk, &empty_circuit).expect("keygen_pk should not fail");
+
+        let mut rng = OsRng;
+        let leaf = Fq::random(&mut rng);
+        let elements = (0..DEPTH).map(|_| Fq::random(&mut rng)).collect::<Vec<_>>();
+        let indices = (0..DEPTH).map(|i| (i % 2) as u64).collect::<Vec<_>>();
+        let digest = compute_merkle_root(&leaf, &elements, &indices);
+
+        let circuit: MerkleTreeCircuit<Fq> = MerkleTreeCircuit {
+            leaf: Value::known(leaf),
+            elements: elements.iter().map(|x| Value::known(*x)).collect(),
+            indices: indices.iter().map(|x| Value::known(Fq::from(*x))).collect(),
+            _spec: PhantomData,
+        };
+        let public_input = vec![leaf, digest];
+
+        // Proving
+        let mut transcript = Blake2bWrite::<_, pallas::Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<IPACommitmentScheme<_>, ProverIPA<_>, _, _, _, _>(
+            &params,
+            &pk,
+            &[circuit],
+            &[&[&public_input]],
+            &mut rng,
+            &mut transcript,
+        )
+        .expect("proof generation should not fail");
+        let proof = transcript.finalize();
+
+        // Verifying
+        let strategy = SingleStrategy::new(&params);
+        let mut transcript = Blake2bRead::<_, _, Challenge255<_>>::init(&proof[..]);
+        let verification_result = verify_proof(
+            &params,
+            pk.get_vk(),
+            strategy,
+            &[&[&public_input]],
+            &mut transcript,
+        );
+        assert!(verification_result.is_ok(), "Verification failed");
+
+        let wrong_public_input = vec![leaf, digest + Fq::ONE];
+        let strategy = SingleStrategy::new(&params);
+        let mut transcript = Blake2bRead::<_, _, Challenge255<_>>::init(&proof[..]);
+        let verification_result = verify_proof(
+            &params,
+            pk.get_vk(),
+            strategy,
+            &[&[&wrong_public_input]],
+            &mut transcript,
+        );
+        assert!(
+            verification_result.is_err(),
+            "Verification succeded when it should have failed"
+        );
+    }
+
+    // Same as `test_merkle_proving_and_verifying`, but over BN254 with KZG commitments, opened with both SHPLONK and
+    // GWC
+    #[test]
+    fn test_kzg_proving_and_verifying() {
+        const DEPTH: usize = 20;
+        const K: u32 = 10;
+
+        // Initialization
+        let mut rng = OsRng;
+        let params = ParamsKZG::<Bn256>::setup(K, &mut rng);
+        let empty_circuit: MerkleTreeCircuit<Fr> = MerkleTreeCircuit {
+            leaf: Value::unknown(),
+            elements: vec![Value::unknown(); DEPTH],
+            indices: vec![Value::unknown(); DEPTH],
+            _spec: PhantomData,
+        };
+        let vk = keygen_vk(&params, &empty_circuit).expect("keygen_vk should not fail");
+        let pk = keygen_pk(&params, vk, &empty_circuit).expect("keygen_pk should not fail");
+
+        let leaf = Fr::random(&mut rng);
+        let elements = (0..DEPTH).map(|_| Fr::random(&mut rng)).collect::<Vec<_>>();
+        let indices = (0..DEPTH).map(|i| (i % 2) as u64).collect::<Vec<_>>();
+        let digest =
+            compute_merkle_root_with::<_, OrchardNullifier, 3, 2, 2>(&leaf, &elements, &indices);
+
+        let circuit: MerkleTreeCircuit<Fr> = MerkleTreeCircuit {
+            leaf: Value::known(leaf),
+            elements: elements.iter().map(|x| Value::known(*x)).collect(),
+            indices: indices.iter().map(|x| Value::known(Fr::from(*x))).collect(),
+            _spec: PhantomData,
+        };
+        let public_input = vec![leaf, digest];
+        let wrong_public_input = vec![leaf, Fr::from(432058235)];
+
+        let prover = MockProver::run(K, &circuit, vec![public_input.clone()]).unwrap();
+        prover.assert_satisfied();
+
+        // SHPLONK
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<_>, _, _, _, _>(
+            &params,
+            &pk,
+            &[circuit.clone()],
+            &[&[&public_input]],
+            &mut rng,
+            &mut transcript,
+        )
+        .expect("proof generation should not fail");
+        let proof = transcript.finalize();
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        let verification_result =
+            verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<_>, _, _, _>(
+                &params,
+                pk.get_vk(),
+                KZGSingleStrategy::new(&params),
+                &[&[&public_input]],
+                &mut transcript,
+            );
+        assert!(verification_result.is_ok(), "Verification failed");
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        let verification_result =
+            verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<_>, _, _, _>(
+                &params,
+                pk.get_vk(),
+                KZGSingleStrategy::new(&params),
+                &[&[&wrong_public_input]],
+                &mut transcript,
+            );
+        assert!(
+            verification_result.is_err(),
+            "Verification succeded when it should have failed"
+        );
+
+        // GWC
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverGWC<_>, _, _, _, _>(
+            &params,
+            &pk,
+            &[circuit],
+            &[&[&public_input]],
+            &mut rng,
+            &mut transcript,
+        )
+        .expect("proof generation should not fail");
+        let proof = transcript.finalize();
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        let verification_result = verify_proof::<KZGCommitmentScheme<Bn256>, VerifierGWC<_>, _, _, _>(
+            &params,
+            pk.get_vk(),
+            KZGSingleStrategy::new(&params),
+            &[&[&public_input]],
+            &mut transcript,
+        );
+        assert!(verification_result.is_ok(), "Verification failed");
+    }
 }
@@ -0,0 +1,199 @@
+/*
+Proves several leaves against the same root with a multiproof: the nodes their paths have in common are only hashed
+once, and only the siblings that cannot be recomputed from the leaves are witnessed.
+*/
+
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::circuits::merkle::MerkleTreeCircuit;
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+/// Checks a `MerkleMultiproof` for the leaves at `indices` of a tree of `depth`.
+///
+/// The indices decide which nodes get hashed together, so they are part of the circuit rather than of its witness:
+/// the keys of the circuit are specific to a set of indices, and the directions of the layers are constants.
+///
+/// The public inputs are the leaves in rows `0..n`, followed by the root in row `n`.
+#[derive(Clone, Default)]
+pub struct MerkleMultiproofCircuit {
+    pub depth: usize,
+    pub indices: Vec<u64>,
+    pub leaves: Vec<Value<Fp>>,
+    pub siblings: Vec<Value<Fp>>,
+}
+
+impl Circuit<Fp> for MerkleMultiproofCircuit {
+    type Config = MerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            depth: self.depth,
+            indices: self.indices.clone(),
+            leaves: vec![Value::unknown(); self.leaves.len()],
+            siblings: vec![Value::unknown(); self.siblings.len()],
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        <MerkleTreeCircuit>::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        assert!(
+            !self.indices.is_empty(),
+            "there has to be at least one leaf to prove"
+        );
+        assert!(
+            self.indices.windows(2).all(|pair| pair[0] < pair[1]),
+            "indices have to be sorted and distinct"
+        );
+        assert!(
+            self.indices.iter().all(|index| *index < 1 << self.depth),
+            "indices have to be in the tree"
+        );
+        assert_eq!(self.indices.len(), self.leaves.len());
+        let chip = MerkleTreeChip::construct(config.clone());
+        let poseidon_chip =
+            PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(config.poseidon_config);
+
+        let mut nodes = vec![];
+        for (i, (index, leaf)) in self.indices.iter().zip(&self.leaves).enumerate() {
+            let leaf_cell =
+                chip.load_private(layouter.namespace(|| format!("load leaf {}", i)), *leaf)?;
+            chip.expose_public(
+                layouter.namespace(|| format!("public leaf {}", i)),
+                &leaf_cell,
+                i,
+            )?;
+            nodes.push((*index, leaf_cell));
+        }
+
+        let left = chip.load_constant(layouter.namespace(|| "left"), Fp::zero())?;
+        let right = chip.load_constant(layouter.namespace(|| "right"), Fp::one())?;
+        let mut siblings = self.siblings.iter();
+        for layer in 0..self.depth {
+            let mut next = vec![];
+            let mut i = 0;
+            while i < nodes.len() {
+                let (index, node) = &nodes[i];
+                let digest = match nodes.get(i + 1) {
+                    // Both children are known: hash them directly, there is nothing to swap
+                    Some((next_index, next_node)) if index & 1 == 0 && *next_index == index + 1 => {
+                        i += 1;
+                        poseidon_chip.hash(
+                            layouter.namespace(|| format!("node {} {}", layer + 1, index >> 1)),
+                            &[node.clone(), next_node.clone()],
+                        )?
+                    }
+                    _ => {
+                        let sibling = siblings.next().expect("missing sibling");
+                        chip.merkle_prove_layer_with_bit(
+                            layouter.namespace(|| format!("node {} {}", layer + 1, index >> 1)),
+                            node,
+                            std::slice::from_ref(sibling),
+                            if index & 1 == 0 { &left } else { &right },
+                        )?
+                    }
+                };
+                next.push((index >> 1, digest));
+                i += 1;
+            }
+            nodes = next;
+        }
+        assert!(siblings.next().is_none(), "too many siblings");
+        assert_eq!(nodes.len(), 1, "the leaves do not lead to a single root");
+
+        chip.expose_public(
+            layouter.namespace(|| "public root"),
+            &nodes[0].1,
+            self.leaves.len(),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuits::merkle::MultiMerkleTreeCircuit;
+    use crate::trees::merkle::MerkleTree;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    fn tree() -> (Vec<Fp>, MerkleTree) {
+        let leaves = (0..40).map(|_| Fp::random(OsRng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(10, leaves.clone());
+        (leaves, tree)
+    }
+
+    #[test]
+    fn test_multiproof() {
+        let (leaves, tree) = tree();
+        for indices in [vec![4, 5, 6, 7], vec![0, 3, 39, 700], vec![9]] {
+            let multiproof = tree.multiproof(&indices);
+            let public_input = multiproof.public_inputs(tree.root());
+            let circuit = MerkleMultiproofCircuit::from(multiproof);
+            let prover = MockProver::run(11, &circuit, vec![public_input]).unwrap();
+            prover.assert_satisfied();
+        }
+
+        // Neither a wrong leaf nor a wrong root go through
+        let multiproof = tree.multiproof(&[4, 5, 6, 7]);
+        let circuit = MerkleMultiproofCircuit::from(multiproof.clone());
+        let mut public_input = multiproof.public_inputs(tree.root());
+        public_input[1] = leaves[8];
+        let prover = MockProver::run(11, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+
+        let public_input = multiproof.public_inputs(Fp::random(OsRng));
+        let prover = MockProver::run(11, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // The layer directions come from the circuit: a multiproof for other positions does not check out
+    #[test]
+    fn test_indices_are_fixed() {
+        let (_, tree) = tree();
+        let multiproof = tree.multiproof(&[4, 5, 6, 7]);
+        let public_input = multiproof.public_inputs(tree.root());
+        let mut circuit = MerkleMultiproofCircuit::from(multiproof);
+        circuit.indices = vec![8, 9, 10, 11];
+
+        let prover = MockProver::run(11, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // A leaf past the end of the tree would be left out of the root while still being a public input
+    #[test]
+    #[should_panic]
+    fn test_index_out_of_range() {
+        let (_, tree) = tree();
+        let multiproof = tree.multiproof(&[4, 5, 6, 7]);
+        let public_input = multiproof.public_inputs(tree.root());
+        let mut circuit = MerkleMultiproofCircuit::from(multiproof);
+        circuit.indices = vec![4, 5, 6, 7 + (1 << 10)];
+
+        MockProver::run(11, &circuit, vec![public_input]).unwrap();
+    }
+
+    // Adjacent leaves share everything but the bottom of their paths
+    #[test]
+    fn test_witness_size() {
+        let (_, tree) = tree();
+        let indices = [4, 5, 6, 7];
+        let multiproof = MerkleMultiproofCircuit::from(tree.multiproof(&indices));
+        let paths =
+            MultiMerkleTreeCircuit::from(indices.iter().map(|i| tree.path(*i)).collect::<Vec<_>>());
+        assert_eq!(multiproof.siblings.len(), 8);
+        assert_eq!(paths.elements.concat().len(), 40);
+    }
+}
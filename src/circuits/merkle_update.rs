@@ -0,0 +1,165 @@
+/*
+Proves a state transition of a merkle tree: a single leaf, at a position that stays private, was replaced and the root
+moved from `old_root` to `new_root`.
+*/
+
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+
+/// Replaces `old_leaf` with `new_leaf`. Both roots are computed from the same sibling and direction cells, so the
+/// rest of the tree is untouched by the update.
+///
+/// The public inputs are `[old_root, new_root]`.
+#[derive(Clone, Default)]
+pub struct MerkleUpdateCircuit {
+    pub old_leaf: Value<Fp>,
+    pub new_leaf: Value<Fp>,
+    pub elements: Vec<Value<Fp>>,
+    pub indices: Vec<Value<Fp>>,
+}
+
+impl Circuit<Fp> for MerkleUpdateCircuit {
+    type Config = MerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            indices: vec![Value::unknown(); self.indices.len()],
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config);
+
+        // The swap rows both paths are copied into check that the directions are boolean
+        let elements = self
+            .elements
+            .iter()
+            .enumerate()
+            .map(|(i, element)| {
+                chip.load_private(
+                    layouter.namespace(|| format!("load element {}", i)),
+                    *element,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let bits = self
+            .indices
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                chip.load_private(layouter.namespace(|| format!("load index {}", i)), *index)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let old_leaf = chip.load_private(layouter.namespace(|| "load old leaf"), self.old_leaf)?;
+        let old_root = chip.merkle_prove_with_cells(
+            layouter.namespace(|| "old root"),
+            &old_leaf,
+            &elements,
+            &bits,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public old root"), &old_root, 0)?;
+
+        let new_leaf = chip.load_private(layouter.namespace(|| "load new leaf"), self.new_leaf)?;
+        let new_root = chip.merkle_prove_with_cells(
+            layouter.namespace(|| "new root"),
+            &new_leaf,
+            &elements,
+            &bits,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public new root"), &new_root, 1)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::merkle::MerkleTree;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    const K: u32 = 11;
+
+    fn tree() -> MerkleTree {
+        let leaves = (0..20).map(|_| Fp::random(OsRng)).collect::<Vec<_>>();
+        MerkleTree::new(10, leaves)
+    }
+
+    #[test]
+    fn test_update() {
+        let mut tree = tree();
+
+        // Replace an existing leaf, then fill an empty one
+        for index in [7, 300] {
+            let old_root = tree.root();
+            let update = tree.update(index, Fp::random(OsRng));
+            assert_eq!(update.old_root(), old_root);
+            assert_eq!(update.new_root(), tree.root());
+
+            let public_input = vec![update.old_root(), update.new_root()];
+            let circuit = MerkleUpdateCircuit::from(update);
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            prover.assert_satisfied();
+        }
+    }
+
+    #[test]
+    fn test_wrong_roots() {
+        let mut tree = tree();
+        let update = tree.update(3, Fp::random(OsRng));
+        let (old_root, new_root) = (update.old_root(), update.new_root());
+        let circuit = MerkleUpdateCircuit::from(update);
+
+        for public_input in [
+            vec![new_root, old_root],
+            vec![old_root, Fp::random(OsRng)],
+            vec![Fp::random(OsRng), new_root],
+        ] {
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    // Siblings that differ between the two paths would let the update rewrite the rest of the tree
+    #[test]
+    fn test_siblings_are_shared() {
+        let mut tree = tree();
+        let before = tree.clone();
+        let update = tree.update(3, Fp::random(OsRng));
+        let new_leaf = update.new_leaf;
+
+        // Claim a new root in which another leaf changed as well
+        tree.set(12, Fp::random(OsRng));
+        let public_input = vec![before.root(), tree.root()];
+        let mut circuit = MerkleUpdateCircuit::from(update);
+        circuit.elements = tree
+            .path(3)
+            .elements
+            .into_iter()
+            .map(Value::known)
+            .collect();
+        assert_eq!(tree.leaf(3), new_leaf);
+
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -4,6 +4,7 @@ is already implemented in halo2_gadgets, there is no wrapper chip that makes it
 */
 
 use super::super::chips::poseidon::{PoseidonChip, PoseidonConfig};
+use ff::PrimeField;
 use halo2_gadgets::poseidon::{
     primitives::{Spec},
 };
@@ -14,32 +15,37 @@ use halo2_proofs::{
         ConstraintSystem, Error,
     },
 };
-use halo2curves::pasta::{Fp};
 use std::marker::PhantomData;
 
 #[derive(Debug, Clone, Copy)]
 pub struct PoseidonCircuit<
-    S: Spec<Fp, WIDTH, RATE>,
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
     const WIDTH: usize,
     const RATE: usize,
     const L: usize,
 > {
-    pub message: [Value<Fp>; L],
-    pub output: Value<Fp>,
+    pub message: [Value<F>; L],
+    pub output: Value<F>,
     pub _spec: PhantomData<S>,
 }
 
-impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: usize> Circuit<Fp>
-    for PoseidonCircuit<S, WIDTH, RATE, L>
+impl<
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE>,
+        const WIDTH: usize,
+        const RATE: usize,
+        const L: usize,
+    > Circuit<F> for PoseidonCircuit<F, S, WIDTH, RATE, L>
 {
-    type Config = PoseidonConfig<WIDTH, RATE, L>;
+    type Config = PoseidonConfig<F, WIDTH, RATE, L>;
     type FloorPlanner = SimpleFloorPlanner;
 
     fn without_witnesses(&self) -> Self {
         Self {
             message: (0..L)
                 .map(|_i| Value::unknown())
-                .collect::<Vec<Value<Fp>>>()
+                .collect::<Vec<Value<F>>>()
                 .try_into()
                 .unwrap(),
             output: Value::unknown(),
@@ -47,16 +53,17 @@ impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: u
         }
     }
 
-    fn configure(meta: &mut ConstraintSystem<Fp>) -> PoseidonConfig<WIDTH, RATE, L> {
-        PoseidonChip::<S, WIDTH, RATE, L>::configure(meta)
+    fn configure(meta: &mut ConstraintSystem<F>) -> PoseidonConfig<F, WIDTH, RATE, L> {
+        let instance = meta.instance_column();
+        PoseidonChip::<F, S, WIDTH, RATE, L>::configure(meta, Some(instance))
     }
 
     fn synthesize(
         &self,
-        config: PoseidonConfig<WIDTH, RATE, L>,
-        mut layouter: impl Layouter<Fp>,
+        config: PoseidonConfig<F, WIDTH, RATE, L>,
+        mut layouter: impl Layouter<F>,
     ) -> Result<(), Error> {
-        let poseidon_chip = PoseidonChip::<S, WIDTH, RATE, L>::construct(config);
+        let poseidon_chip = PoseidonChip::<F, S, WIDTH, RATE, L>::construct(config);
         let message_cells = poseidon_chip
             .load_private_inputs(layouter.namespace(|| "load private inputs"), self.message)?;
         let result = poseidon_chip.hash(layouter.namespace(|| "poseidon chip"), &message_cells)?;
@@ -65,15 +72,95 @@ impl<S: Spec<Fp, WIDTH, RATE>, const WIDTH: usize, const RATE: usize, const L: u
     }
 }
 
+/// Hashes the first `length` words of `message`, with `length` a private witness of at most `L`. The public input
+/// is the digest.
+#[derive(Debug, Clone, Copy)]
+pub struct VariableLengthPoseidonCircuit<
+    F: PrimeField,
+    S: Spec<F, WIDTH, RATE>,
+    const WIDTH: usize,
+    const RATE: usize,
+    const L: usize,
+> {
+    pub message: [Value<F>; L],
+    pub length: Value<F>,
+    pub _spec: PhantomData<S>,
+}
+
+impl<
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE>,
+        const WIDTH: usize,
+        const RATE: usize,
+        const L: usize,
+    > Circuit<F> for VariableLengthPoseidonCircuit<F, S, WIDTH, RATE, L>
+{
+    type Config = PoseidonConfig<F, WIDTH, RATE, L>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            message: [Value::unknown(); L],
+            length: Value::unknown(),
+            _spec: PhantomData,
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> PoseidonConfig<F, WIDTH, RATE, L> {
+        let instance = meta.instance_column();
+        PoseidonChip::<F, S, WIDTH, RATE, L>::configure_variable_length(meta, Some(instance))
+    }
+
+    fn synthesize(
+        &self,
+        config: PoseidonConfig<F, WIDTH, RATE, L>,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let length_chip = PoseidonChip::<F, S, WIDTH, RATE, 1>::construct(config.with_length());
+        let poseidon_chip = PoseidonChip::<F, S, WIDTH, RATE, L>::construct(config);
+        let message_cells = poseidon_chip
+            .load_private_inputs(layouter.namespace(|| "load private inputs"), self.message)?;
+        let [length_cell] =
+            length_chip.load_private_inputs(layouter.namespace(|| "load length"), [self.length])?;
+        let result = poseidon_chip.hash_variable_length(
+            layouter.namespace(|| "poseidon chip"),
+            &message_cells,
+            &length_cell,
+        )?;
+        poseidon_chip.expose_public(layouter.namespace(|| "expose result"), &result, 0)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::p128pow5t12::P128Pow5T12;
     use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+    use crate::utils::p128pow5t5::P128Pow5T5;
+    use crate::utils::p128pow5t9::P128Pow5T9;
+    use crate::utils::poseidon::{hash_variable_length, Sponge};
     use halo2_gadgets::poseidon::{
         primitives::{self as poseidon, ConstantLength},
     };
     use halo2_proofs::arithmetic::Field;
     use halo2_proofs::dev::MockProver;
+    use halo2_proofs::halo2curves::bn256::{Bn256, Fr, G1Affine};
+    use halo2_proofs::{
+        plonk::{create_proof, keygen_pk, keygen_vk, verify_proof},
+        poly::{
+            kzg::{
+                commitment::{KZGCommitmentScheme, ParamsKZG},
+                multiopen::{ProverGWC, ProverSHPLONK, VerifierGWC, VerifierSHPLONK},
+                strategy::SingleStrategy as KZGSingleStrategy,
+            },
+            VerificationStrategy,
+        },
+        transcript::{
+            Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
+        },
+    };
+    use halo2curves::pasta::{Fp, Fq};
     use rand_core::OsRng;
     use std::marker::PhantomData;
 
@@ -84,7 +171,7 @@ mod tests {
         let output =
             poseidon::Hash::<_, OrchardNullifier, ConstantLength<2>, 3, 2>::init().hash(message);
 
-        let circuit = PoseidonCircuit::<OrchardNullifier, 3, 2, 2> {
+        let circuit = PoseidonCircuit::<Fp, OrchardNullifier, 3, 2, 2> {
             message: message.map(|x| Value::known(x)),
             output: Value::known(output),
             _spec: PhantomData,
@@ -93,4 +180,305 @@ mod tests {
         let prover = MockProver::run(10, &circuit, vec![public_input.clone()]).unwrap();
         prover.assert_satisfied();
     }
+
+    #[test]
+    fn test_fq() {
+        let mut rng = OsRng;
+        let message = [Fq::random(&mut rng), Fq::random(&mut rng)];
+        let output =
+            poseidon::Hash::<_, OrchardNullifier, ConstantLength<2>, 3, 2>::init().hash(message);
+
+        let circuit = PoseidonCircuit::<Fq, OrchardNullifier, 3, 2, 2> {
+            message: message.map(|x| Value::known(x)),
+            output: Value::known(output),
+            _spec: PhantomData,
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        prover.assert_satisfied();
+    }
+
+    #[test]
+    fn test_bn256() {
+        let mut rng = OsRng;
+        let message = [Fr::random(&mut rng), Fr::random(&mut rng)];
+        let output =
+            poseidon::Hash::<_, OrchardNullifier, ConstantLength<2>, 3, 2>::init().hash(message);
+
+        let circuit = PoseidonCircuit::<Fr, OrchardNullifier, 3, 2, 2> {
+            message: message.map(|x| Value::known(x)),
+            output: Value::known(output),
+            _spec: PhantomData,
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        prover.assert_satisfied();
+
+        let prover = MockProver::run(10, &circuit, vec![vec![output + Fr::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Prove and verify the BN254 circuit with KZG commitments, opened with both SHPLONK and GWC
+    #[test]
+    fn test_kzg_proving_and_verifying() {
+        const K: u32 = 7;
+
+        let mut rng = OsRng;
+        let params = ParamsKZG::<Bn256>::setup(K, &mut rng);
+        let empty_circuit = PoseidonCircuit::<Fr, OrchardNullifier, 3, 2, 2> {
+            message: [Value::unknown(); 2],
+            output: Value::unknown(),
+            _spec: PhantomData,
+        };
+        let vk = keygen_vk(&params, &empty_circuit).expect("keygen_vk should not fail");
+        let pk = keygen_pk(&params, vk, &empty_circuit).expect("keygen_pk should not fail");
+
+        let message = [Fr::random(&mut rng), Fr::random(&mut rng)];
+        let output =
+            poseidon::Hash::<_, OrchardNullifier, ConstantLength<2>, 3, 2>::init().hash(message);
+        let circuit = PoseidonCircuit::<Fr, OrchardNullifier, 3, 2, 2> {
+            message: message.map(|x| Value::known(x)),
+            output: Value::known(output),
+            _spec: PhantomData,
+        };
+        let wrong_output = output + Fr::ONE;
+
+        // SHPLONK
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<_>, _, _, _, _>(
+            &params,
+            &pk,
+            &[circuit],
+            &[&[&[output]]],
+            &mut rng,
+            &mut transcript,
+        )
+        .expect("proof generation should not fail");
+        let proof = transcript.finalize();
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        let verification_result =
+            verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<_>, _, _, _>(
+                &params,
+                pk.get_vk(),
+                KZGSingleStrategy::new(&params),
+                &[&[&[output]]],
+                &mut transcript,
+            );
+        assert!(verification_result.is_ok(), "Verification failed");
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        let verification_result =
+            verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<_>, _, _, _>(
+                &params,
+                pk.get_vk(),
+                KZGSingleStrategy::new(&params),
+                &[&[&[wrong_output]]],
+                &mut transcript,
+            );
+        assert!(
+            verification_result.is_err(),
+            "Verification succeded when it should have failed"
+        );
+
+        // GWC
+        let mut transcript = Blake2bWrite::<_, G1Affine, Challenge255<_>>::init(vec![]);
+        create_proof::<KZGCommitmentScheme<Bn256>, ProverGWC<_>, _, _, _, _>(
+            &params,
+            &pk,
+            &[circuit],
+            &[&[&[output]]],
+            &mut rng,
+            &mut transcript,
+        )
+        .expect("proof generation should not fail");
+        let proof = transcript.finalize();
+
+        let mut transcript = Blake2bRead::<_, G1Affine, Challenge255<_>>::init(&proof[..]);
+        let verification_result = verify_proof::<KZGCommitmentScheme<Bn256>, VerifierGWC<_>, _, _, _>(
+            &params,
+            pk.get_vk(),
+            KZGSingleStrategy::new(&params),
+            &[&[&[output]]],
+            &mut transcript,
+        );
+        assert!(verification_result.is_ok(), "Verification failed");
+    }
+
+    // Hash a random message natively and in the circuit, with a spec of any width
+    fn assert_hash<F, S, const WIDTH: usize, const RATE: usize, const L: usize>()
+    where
+        F: PrimeField,
+        S: Spec<F, WIDTH, RATE>,
+    {
+        let mut rng = OsRng;
+        let message: [F; L] = [(); L].map(|_| F::random(&mut rng));
+        let output = poseidon::Hash::<_, S, ConstantLength<L>, WIDTH, RATE>::init().hash(message);
+
+        let circuit = PoseidonCircuit::<F, S, WIDTH, RATE, L> {
+            message: message.map(|x| Value::known(x)),
+            output: Value::known(output),
+            _spec: PhantomData,
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        prover.assert_satisfied();
+
+        let prover = MockProver::run(10, &circuit, vec![vec![output + F::ONE]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_width_5() {
+        assert_hash::<Fp, P128Pow5T5, 5, 4, 4>();
+        assert_hash::<Fq, P128Pow5T5, 5, 4, 4>();
+        // Longer than the rate, so the message takes several permutations
+        assert_hash::<Fp, P128Pow5T5, 5, 4, 7>();
+    }
+
+    #[test]
+    fn test_width_9() {
+        assert_hash::<Fp, P128Pow5T9, 9, 8, 8>();
+        assert_hash::<Fq, P128Pow5T9, 9, 8, 8>();
+    }
+
+    #[test]
+    fn test_width_12() {
+        assert_hash::<Fp, P128Pow5T12, 12, 11, 11>();
+        assert_hash::<Fq, P128Pow5T12, 12, 11, 11>();
+    }
+
+    fn variable_length_circuit<S: Spec<Fp, 3, 2>>(
+        message: &[Fp; 5],
+        length: usize,
+    ) -> VariableLengthPoseidonCircuit<Fp, S, 3, 2, 5> {
+        VariableLengthPoseidonCircuit {
+            message: message.map(|x| Value::known(x)),
+            length: Value::known(Fp::from(length as u64)),
+            _spec: PhantomData,
+        }
+    }
+
+    #[test]
+    fn test_variable_length() {
+        let mut rng = OsRng;
+        let message = [(); 5].map(|_| Fp::random(&mut rng));
+        for length in 0..=5 {
+            let output = hash_variable_length::<Fp, OrchardNullifier, 3, 2>(&message[..length]);
+            let circuit = variable_length_circuit::<OrchardNullifier>(&message, length);
+            let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+            prover.assert_satisfied();
+        }
+
+        // The words past the length do not change the digest
+        let output = hash_variable_length::<Fp, OrchardNullifier, 3, 2>(&message[..3]);
+        let mut other_message = message;
+        other_message[4] = Fp::random(&mut rng);
+        let circuit = variable_length_circuit::<OrchardNullifier>(&other_message, 3);
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        prover.assert_satisfied();
+
+        // The length is bound to the digest
+        let circuit = variable_length_circuit::<OrchardNullifier>(&message, 4);
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_variable_length_out_of_range() {
+        let message = [(); 5].map(|_| Fp::random(OsRng));
+        let output = hash_variable_length::<Fp, OrchardNullifier, 3, 2>(&message);
+        let circuit = variable_length_circuit::<OrchardNullifier>(&message, 6);
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_variable_length_wide() {
+        let message = [(); 8].map(|_| Fq::random(OsRng));
+        let output = hash_variable_length::<Fq, P128Pow5T9, 9, 8>(&message[..6]);
+        let circuit = VariableLengthPoseidonCircuit::<Fq, P128Pow5T9, 9, 8, 8> {
+            message: message.map(|x| Value::known(x)),
+            length: Value::known(Fq::from(6)),
+            _spec: PhantomData,
+        };
+        let prover = MockProver::run(10, &circuit, vec![vec![output]]).unwrap();
+        prover.assert_satisfied();
+    }
+
+    // Absorbs the message, squeezes two outputs, absorbs the first word again and squeezes a third output
+    #[derive(Clone)]
+    struct SpongeCircuit {
+        message: [Value<Fp>; 3],
+        domain: Fp,
+    }
+
+    impl Circuit<Fp> for SpongeCircuit {
+        type Config = PoseidonConfig<Fp, 3, 2, 3>;
+        type FloorPlanner = SimpleFloorPlanner;
+
+        fn without_witnesses(&self) -> Self {
+            Self {
+                message: [Value::unknown(); 3],
+                domain: self.domain,
+            }
+        }
+
+        fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+            let instance = meta.instance_column();
+            PoseidonChip::<Fp, OrchardNullifier, 3, 2, 3>::configure_variable_length(
+                meta,
+                Some(instance),
+            )
+        }
+
+        fn synthesize(
+            &self,
+            config: Self::Config,
+            mut layouter: impl Layouter<Fp>,
+        ) -> Result<(), Error> {
+            let chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, 3>::construct(config);
+            let message = chip.load_private_inputs(layouter.namespace(|| "load"), self.message)?;
+            let mut sponge = chip.sponge(layouter.namespace(|| "sponge"), self.domain)?;
+            for (i, word) in message.iter().enumerate() {
+                sponge.absorb(layouter.namespace(|| format!("absorb {}", i)), word)?;
+            }
+            let mut outputs = vec![];
+            for i in 0..2 {
+                outputs.push(sponge.squeeze(layouter.namespace(|| format!("squeeze {}", i)))?);
+            }
+            sponge.absorb(layouter.namespace(|| "absorb again"), &message[0])?;
+            outputs.push(sponge.squeeze(layouter.namespace(|| "squeeze again"))?);
+
+            for (i, output) in outputs.iter().enumerate() {
+                chip.expose_public(layouter.namespace(|| format!("output {}", i)), output, i)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sponge() {
+        let message = [(); 3].map(|_| Fp::random(OsRng));
+        let domain = Fp::from(42);
+        let mut sponge = Sponge::<Fp, OrchardNullifier, 3, 2>::new(domain);
+        for word in message {
+            sponge.absorb(word);
+        }
+        let mut outputs = vec![sponge.squeeze(), sponge.squeeze()];
+        sponge.absorb(message[0]);
+        outputs.push(sponge.squeeze());
+
+        let circuit = SpongeCircuit {
+            message: message.map(|x| Value::known(x)),
+            domain,
+        };
+        let prover = MockProver::run(10, &circuit, vec![outputs.clone()]).unwrap();
+        prover.assert_satisfied();
+
+        // The domain is part of the circuit
+        let circuit = SpongeCircuit {
+            domain: domain + Fp::ONE,
+            ..circuit
+        };
+        let prover = MockProver::run(10, &circuit, vec![outputs]).unwrap();
+        assert!(prover.verify().is_err());
+    }
 }
@@ -0,0 +1,235 @@
+/*
+Rate-limiting nullifiers: a member may publish one message per epoch. Every message comes with a Shamir share of the
+member's secret on a line that only depends on the secret and the epoch, so two messages in the same epoch reveal the
+secret to anyone, who can then slash the member.
+*/
+
+use ff::Field;
+use halo2_gadgets::poseidon::primitives::{self as poseidon, ConstantLength};
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+use rand_core::RngCore;
+
+use crate::chips::arithmetic::{ArithmeticChip, ArithmeticConfig};
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::trees::merkle::{hash_nodes, MerklePath};
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+fn hash_one(x: Fp) -> Fp {
+    poseidon::Hash::<_, OrchardNullifier, ConstantLength<1>, 3, 2>::init().hash([x])
+}
+
+/// A member of the tree, identified by the leaf `Poseidon(secret)`
+#[derive(Debug, Clone, Copy)]
+pub struct RlnIdentity {
+    pub secret: Fp,
+}
+
+impl RlnIdentity {
+    pub fn random(rng: impl RngCore) -> Self {
+        Self {
+            secret: Fp::random(rng),
+        }
+    }
+
+    /// The leaf registering this member in the tree
+    pub fn commitment(&self) -> Fp {
+        hash_one(self.secret)
+    }
+
+    /// The share revealed when sending the message hashing to `x` in `epoch`
+    pub fn share(&self, epoch: Fp, x: Fp) -> RlnShare {
+        let slope = hash_nodes(self.secret, epoch);
+        RlnShare {
+            epoch,
+            x,
+            y: self.secret + slope * x,
+            nullifier: hash_one(slope),
+        }
+    }
+
+    /// The circuit showing that this member sits at the end of `path` and produces `self.share(epoch, x)`
+    pub fn circuit(&self, path: &MerklePath, epoch: Fp, x: Fp) -> RlnCircuit {
+        RlnCircuit {
+            secret: Value::known(self.secret),
+            elements: path.elements.iter().map(|x| Value::known(*x)).collect(),
+            indices: path
+                .indices
+                .iter()
+                .map(|x| Value::known(Fp::from(*x)))
+                .collect(),
+            epoch: Value::known(epoch),
+            x: Value::known(x),
+        }
+    }
+}
+
+/// The point `(x, y)` a member reveals on the line of `epoch`, and the nullifier tying together all the shares of a
+/// member within that epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RlnShare {
+    pub epoch: Fp,
+    pub x: Fp,
+    pub y: Fp,
+    pub nullifier: Fp,
+}
+
+impl RlnShare {
+    /// The public inputs of the `RlnCircuit` producing this share under `root`
+    pub fn public_inputs(&self, root: Fp) -> Vec<Fp> {
+        vec![root, self.epoch, self.x, self.y, self.nullifier]
+    }
+}
+
+/// Recover the secret of a member from two shares of the same epoch for different messages. Returns `None` if the
+/// shares do not come from the same member and epoch, or do not pin down the line.
+pub fn slash(first: &RlnShare, second: &RlnShare) -> Option<Fp> {
+    if first.nullifier != second.nullifier || first.epoch != second.epoch || first.x == second.x {
+        return None;
+    }
+    // Both shares sit on y = secret + slope * x
+    let slope = (first.y - second.y) * (first.x - second.x).invert().unwrap();
+    Some(first.y - slope * first.x)
+}
+
+#[derive(Debug, Clone)]
+pub struct RlnConfig {
+    pub merkle_config: MerkleTreeConfig,
+    pub arithmetic_config: ArithmeticConfig,
+}
+
+/// Proves that a member of the tree produced a share for message hash `x` in `epoch`: with `slope = Poseidon(secret,
+/// epoch)`, the share is `y = secret + slope * x` and the nullifier is `Poseidon(slope)`.
+///
+/// The public inputs are `[root, epoch, x, y, nullifier]`.
+#[derive(Clone, Default)]
+pub struct RlnCircuit {
+    pub secret: Value<Fp>,
+    pub elements: Vec<Value<Fp>>,
+    pub indices: Vec<Value<Fp>>,
+    pub epoch: Value<Fp>,
+    pub x: Value<Fp>,
+}
+
+impl Circuit<Fp> for RlnCircuit {
+    type Config = RlnConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            indices: vec![Value::unknown(); self.indices.len()],
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        RlnConfig {
+            merkle_config: MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance),
+            arithmetic_config: ArithmeticChip::configure(meta, [col_a, col_b, col_c]),
+        }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config.merkle_config.clone());
+        let arithmetic_chip = ArithmeticChip::construct(config.arithmetic_config);
+        let poseidon_config = config.merkle_config.poseidon_config;
+        let hash_one_chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, 1>::construct(
+            poseidon_config.with_length(),
+        );
+        let hash_two_chip =
+            PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(poseidon_config);
+
+        // Membership
+        let secret = chip.load_private(layouter.namespace(|| "load secret"), self.secret)?;
+        let leaf = hash_one_chip.hash(layouter.namespace(|| "commitment"), &[secret.clone()])?;
+        let digest = chip.merkle_prove(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf,
+            &self.elements,
+            &self.indices,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, 0)?;
+
+        // Share
+        let epoch = chip.load_private(layouter.namespace(|| "load epoch"), self.epoch)?;
+        chip.expose_public(layouter.namespace(|| "public epoch"), &epoch, 1)?;
+        let x = chip.load_private(layouter.namespace(|| "load x"), self.x)?;
+        chip.expose_public(layouter.namespace(|| "public x"), &x, 2)?;
+        let slope = hash_two_chip.hash(layouter.namespace(|| "slope"), &[secret.clone(), epoch])?;
+        let y = arithmetic_chip.mul_add(layouter.namespace(|| "share"), &secret, &slope, &x)?;
+        chip.expose_public(layouter.namespace(|| "public y"), &y, 3)?;
+
+        let nullifier = hash_one_chip.hash(layouter.namespace(|| "nullifier"), &[slope])?;
+        chip.expose_public(layouter.namespace(|| "public nullifier"), &nullifier, 4)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::merkle::MerkleTree;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    const DEPTH: usize = 10;
+
+    fn members() -> (Vec<RlnIdentity>, MerkleTree) {
+        let members = (0..8).map(|_| RlnIdentity::random(OsRng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(DEPTH, members.iter().map(RlnIdentity::commitment).collect());
+        (members, tree)
+    }
+
+    #[test]
+    fn test_rln() {
+        let (members, tree) = members();
+        let (epoch, x) = (Fp::from(7), Fp::random(OsRng));
+        let member = members[2];
+
+        let circuit = member.circuit(&tree.path(2), epoch, x);
+        let share = member.share(epoch, x);
+        let prover = MockProver::run(11, &circuit, vec![share.public_inputs(tree.root())]).unwrap();
+        prover.assert_satisfied();
+
+        // The share and the nullifier have to be the ones of the member
+        for wrong_share in [
+            RlnShare { y: share.y + Fp::one(), ..share },
+            RlnShare { nullifier: Fp::random(OsRng), ..share },
+            members[3].share(epoch, x),
+        ] {
+            let prover =
+                MockProver::run(11, &circuit, vec![wrong_share.public_inputs(tree.root())])
+                    .unwrap();
+            assert!(prover.verify().is_err());
+        }
+    }
+
+    #[test]
+    fn test_slash() {
+        let member = RlnIdentity::random(OsRng);
+        let epoch = Fp::from(7);
+        let first = member.share(epoch, Fp::random(OsRng));
+        let second = member.share(epoch, Fp::random(OsRng));
+        assert_eq!(first.nullifier, second.nullifier);
+        assert_eq!(slash(&first, &second), Some(member.secret));
+
+        // One message per epoch is fine
+        let next = member.share(Fp::from(8), Fp::random(OsRng));
+        assert_ne!(first.nullifier, next.nullifier);
+        assert_eq!(slash(&first, &next), None);
+
+        // Shares of different members cannot be combined
+        let other = RlnIdentity::random(OsRng).share(epoch, Fp::random(OsRng));
+        assert_eq!(slash(&first, &other), None);
+    }
+}
@@ -0,0 +1,314 @@
+/*
+Semaphore-style signalling: a member of the tree broadcasts a signal without revealing who they are. Every proof is
+bound to the hash of the signal and to an external nullifier (the topic of the poll, the epoch, ...), and reveals a
+nullifier hash that is the same for all the signals of a member under the same external nullifier.
+*/
+
+use ff::Field;
+use halo2_gadgets::poseidon::primitives::{self as poseidon, ConstantLength};
+use halo2_proofs::{
+    circuit::*,
+    plonk::*,
+    poly::{
+        ipa::{
+            commitment::{IPACommitmentScheme, ParamsIPA},
+            multiopen::ProverIPA,
+            strategy::SingleStrategy,
+        },
+        VerificationStrategy,
+    },
+    transcript::{
+        Blake2bRead, Blake2bWrite, Challenge255, TranscriptReadBuffer, TranscriptWriterBuffer,
+    },
+};
+use halo2curves::pasta::{vesta, Fp};
+use rand_core::RngCore;
+
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::trees::merkle::{hash_nodes, MerklePath};
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+/// The two secrets of a member. The tree holds `Poseidon(Poseidon(nullifier, trapdoor))`.
+#[derive(Debug, Clone, Copy)]
+pub struct Identity {
+    pub trapdoor: Fp,
+    pub nullifier: Fp,
+}
+
+impl Identity {
+    pub fn secret(&self) -> Fp {
+        hash_nodes(self.nullifier, self.trapdoor)
+    }
+
+    /// The leaf registering this identity in the tree
+    pub fn commitment(&self) -> Fp {
+        poseidon::Hash::<_, OrchardNullifier, ConstantLength<1>, 3, 2>::init().hash([self.secret()])
+    }
+
+    /// The nullifier hash revealed by every signal of this identity under `external_nullifier`
+    pub fn nullifier_hash(&self, external_nullifier: Fp) -> Fp {
+        hash_nodes(external_nullifier, self.nullifier)
+    }
+
+    /// The circuit signalling `signal_hash` under `external_nullifier` for the identity at the end of `path`
+    pub fn circuit(
+        &self,
+        path: &MerklePath,
+        signal_hash: Fp,
+        external_nullifier: Fp,
+    ) -> SemaphoreCircuit {
+        SemaphoreCircuit {
+            trapdoor: Value::known(self.trapdoor),
+            nullifier: Value::known(self.nullifier),
+            elements: path.elements.iter().map(|x| Value::known(*x)).collect(),
+            indices: path
+                .indices
+                .iter()
+                .map(|x| Value::known(Fp::from(*x)))
+                .collect(),
+            signal_hash: Value::known(signal_hash),
+            external_nullifier: Value::known(external_nullifier),
+        }
+    }
+}
+
+/// Proves that an identity of the tree signals `signal_hash` under `external_nullifier`.
+///
+/// The public inputs are `[root, nullifier_hash, signal_hash, external_nullifier]`.
+#[derive(Clone, Default)]
+pub struct SemaphoreCircuit {
+    pub trapdoor: Value<Fp>,
+    pub nullifier: Value<Fp>,
+    pub elements: Vec<Value<Fp>>,
+    pub indices: Vec<Value<Fp>>,
+    pub signal_hash: Value<Fp>,
+    pub external_nullifier: Value<Fp>,
+}
+
+impl SemaphoreCircuit {
+    /// The circuit without witnesses for a tree of `depth`, used to generate the keys
+    pub fn empty(depth: usize) -> Self {
+        Self {
+            elements: vec![Value::unknown(); depth],
+            indices: vec![Value::unknown(); depth],
+            ..Self::default()
+        }
+    }
+}
+
+impl Circuit<Fp> for SemaphoreCircuit {
+    type Config = MerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self::empty(self.elements.len())
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config.clone());
+        let hash_one_chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, 1>::construct(
+            config.poseidon_config.with_length(),
+        );
+        let hash_two_chip =
+            PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(config.poseidon_config);
+
+        // Membership
+        let trapdoor = chip.load_private(layouter.namespace(|| "load trapdoor"), self.trapdoor)?;
+        let nullifier =
+            chip.load_private(layouter.namespace(|| "load nullifier"), self.nullifier)?;
+        let secret = hash_two_chip.hash(
+            layouter.namespace(|| "secret"),
+            &[nullifier.clone(), trapdoor],
+        )?;
+        let leaf = hash_one_chip.hash(layouter.namespace(|| "commitment"), &[secret])?;
+        let digest = chip.merkle_prove(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf,
+            &self.elements,
+            &self.indices,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, 0)?;
+
+        // Nullifier hash
+        let external_nullifier = chip.load_private(
+            layouter.namespace(|| "load external nullifier"),
+            self.external_nullifier,
+        )?;
+        let nullifier_hash = hash_two_chip.hash(
+            layouter.namespace(|| "nullifier hash"),
+            &[external_nullifier.clone(), nullifier],
+        )?;
+        chip.expose_public(
+            layouter.namespace(|| "public nullifier hash"),
+            &nullifier_hash,
+            1,
+        )?;
+
+        // The signal hash only needs to be an instance cell: the instances are absorbed by the transcript, so the
+        // proof does not verify against any other signal.
+        let signal_hash =
+            chip.load_private(layouter.namespace(|| "load signal hash"), self.signal_hash)?;
+        chip.expose_public(layouter.namespace(|| "public signal hash"), &signal_hash, 2)?;
+        chip.expose_public(
+            layouter.namespace(|| "public external nullifier"),
+            &external_nullifier,
+            3,
+        )?;
+        Ok(())
+    }
+}
+
+/// Generate a fresh identity
+pub fn generate_identity(mut rng: impl RngCore) -> Identity {
+    Identity {
+        trapdoor: Fp::random(&mut rng),
+        nullifier: Fp::random(&mut rng),
+    }
+}
+
+/// Generate the proving key for signalling in trees of `depth`
+pub fn keygen(
+    params: &ParamsIPA<vesta::Affine>,
+    depth: usize,
+) -> Result<ProvingKey<vesta::Affine>, Error> {
+    let empty_circuit = SemaphoreCircuit::empty(depth);
+    let vk = keygen_vk(params, &empty_circuit)?;
+    keygen_pk(params, vk, &empty_circuit)
+}
+
+/// Prove that `identity`, found at the end of `path`, signals `signal_hash` under `external_nullifier`. The proof
+/// verifies against the root of `path` and `identity.nullifier_hash(external_nullifier)`.
+pub fn prove_signal(
+    params: &ParamsIPA<vesta::Affine>,
+    pk: &ProvingKey<vesta::Affine>,
+    identity: &Identity,
+    path: &MerklePath,
+    signal_hash: Fp,
+    external_nullifier: Fp,
+    rng: impl RngCore,
+) -> Result<Vec<u8>, Error> {
+    let circuit = identity.circuit(path, signal_hash, external_nullifier);
+    let public_input = vec![
+        path.root(),
+        identity.nullifier_hash(external_nullifier),
+        signal_hash,
+        external_nullifier,
+    ];
+
+    let mut transcript = Blake2bWrite::<_, vesta::Affine, Challenge255<_>>::init(vec![]);
+    create_proof::<IPACommitmentScheme<_>, ProverIPA<_>, _, _, _, _>(
+        params,
+        pk,
+        &[circuit],
+        &[&[&public_input]],
+        rng,
+        &mut transcript,
+    )?;
+    Ok(transcript.finalize())
+}
+
+/// Verify a proof produced by `prove_signal` for a member of the tree of `root`
+pub fn verify_signal(
+    params: &ParamsIPA<vesta::Affine>,
+    vk: &VerifyingKey<vesta::Affine>,
+    root: Fp,
+    nullifier_hash: Fp,
+    signal_hash: Fp,
+    external_nullifier: Fp,
+    proof: &[u8],
+) -> Result<(), Error> {
+    let public_input = vec![root, nullifier_hash, signal_hash, external_nullifier];
+    let strategy = SingleStrategy::new(params);
+    let mut transcript = Blake2bRead::<_, _, Challenge255<_>>::init(proof);
+    verify_proof(params, vk, strategy, &[&[&public_input]], &mut transcript)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::merkle::MerkleTree;
+    use halo2_proofs::dev::MockProver;
+    use halo2_proofs::poly::commitment::ParamsProver;
+    use rand_core::OsRng;
+
+    const DEPTH: usize = 10;
+    const K: u32 = 10;
+
+    fn identities() -> (Vec<Identity>, MerkleTree) {
+        let identities = (0..8).map(|_| generate_identity(OsRng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(DEPTH, identities.iter().map(Identity::commitment).collect());
+        (identities, tree)
+    }
+
+    #[test]
+    fn test_semaphore() {
+        let (identities, tree) = identities();
+        let (signal_hash, external_nullifier) = (Fp::from(1), Fp::from(2));
+        let identity = identities[6];
+
+        let circuit = identity.circuit(&tree.path(6), signal_hash, external_nullifier);
+        let nullifier_hash = identity.nullifier_hash(external_nullifier);
+        let public_input = vec![tree.root(), nullifier_hash, signal_hash, external_nullifier];
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+
+        // Another identity cannot reuse the path
+        let circuit =
+            generate_identity(OsRng).circuit(&tree.path(6), signal_hash, external_nullifier);
+        let public_input = vec![tree.root(), nullifier_hash, signal_hash, external_nullifier];
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_prove_and_verify_signal() {
+        let (identities, tree) = identities();
+        let (signal_hash, external_nullifier) = (Fp::from(1), Fp::from(2));
+        let identity = identities[1];
+
+        let params = ParamsIPA::<vesta::Affine>::new(K);
+        let pk = keygen(&params, DEPTH).unwrap();
+        let proof = prove_signal(
+            &params,
+            &pk,
+            &identity,
+            &tree.path(1),
+            signal_hash,
+            external_nullifier,
+            OsRng,
+        )
+        .unwrap();
+        let nullifier_hash = identity.nullifier_hash(external_nullifier);
+        let verify = |root, nullifier_hash, signal_hash, external_nullifier| {
+            verify_signal(
+                &params,
+                pk.get_vk(),
+                root,
+                nullifier_hash,
+                signal_hash,
+                external_nullifier,
+                &proof,
+            )
+        };
+        assert!(verify(tree.root(), nullifier_hash, signal_hash, external_nullifier).is_ok());
+
+        // The proof cannot be replayed for another signal, external nullifier or nullifier hash
+        assert!(verify(tree.root(), nullifier_hash, Fp::from(3), external_nullifier).is_err());
+        assert!(verify(tree.root(), nullifier_hash, signal_hash, Fp::from(3)).is_err());
+        assert!(verify(tree.root(), Fp::from(3), signal_hash, external_nullifier).is_err());
+        assert!(verify(Fp::from(3), nullifier_hash, signal_hash, external_nullifier).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::chips::poseidon::PoseidonChip;
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+/// Proves that, in a sparse merkle tree with a public root, the slot of a public `key` either holds a public `value`
+/// or is empty. The path to the slot is given by the bits of `key`, so the depth of the tree is `elements.len()`.
+///
+/// The public inputs are `[key, value, root]` for membership proofs and `[key, root]` for non-membership proofs.
+#[derive(Clone, Default)]
+pub struct SparseMerkleTreeCircuit {
+    pub key: Value<Fp>,
+    /// The value stored under `key`, or `None` to prove that the slot of `key` is empty
+    pub value: Option<Value<Fp>>,
+    pub elements: Vec<Value<Fp>>,
+}
+
+impl Circuit<Fp> for SparseMerkleTreeCircuit {
+    type Config = MerkleTreeConfig;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            key: Value::unknown(),
+            value: self.value.map(|_| Value::unknown()),
+            elements: vec![Value::unknown(); self.elements.len()],
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fp>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fp>,
+    ) -> Result<(), Error> {
+        let chip = MerkleTreeChip::construct(config.clone());
+        let key_cell = chip.load_private(layouter.namespace(|| "load key"), self.key)?;
+        chip.expose_public(layouter.namespace(|| "public key"), &key_cell, 0)?;
+        // The key bits are the directions of the path
+        let bits = chip.decompose(
+            layouter.namespace(|| "decompose key"),
+            &key_cell,
+            self.elements.len(),
+        )?;
+
+        let (leaf_cell, root_row) = match self.value {
+            Some(value) => {
+                let value_cell = chip.load_private(layouter.namespace(|| "load value"), value)?;
+                chip.expose_public(layouter.namespace(|| "public value"), &value_cell, 1)?;
+                let poseidon_chip = PoseidonChip::<Fp, OrchardNullifier, 3, 2, 2>::construct(
+                    config.poseidon_config,
+                );
+                let leaf_cell = poseidon_chip
+                    .hash(layouter.namespace(|| "leaf hash"), &[key_cell, value_cell])?;
+                (leaf_cell, 2)
+            }
+            None => (
+                chip.load_constant(layouter.namespace(|| "empty leaf"), Fp::zero())?,
+                1,
+            ),
+        };
+
+        let digest = chip.merkle_prove_with_bits(
+            layouter.namespace(|| "merkle_prove"),
+            &leaf_cell,
+            &self.elements,
+            &bits,
+        )?;
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, root_row)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::sparse_merkle::SparseMerkleTree;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use rand_core::OsRng;
+
+    fn tree(depth: usize) -> SparseMerkleTree {
+        let mut rng = OsRng;
+        let mut tree = SparseMerkleTree::new(depth);
+        for key in [1u64, 2, 3, 1000, 12345] {
+            tree.insert(Fp::from(key), Fp::random(&mut rng));
+        }
+        tree
+    }
+
+    #[test]
+    fn test_membership() {
+        let tree = tree(16);
+        let proof = tree.proof(Fp::from(1000));
+        assert!(proof.value.is_some());
+
+        let public_input = proof.public_inputs();
+        let circuit = SparseMerkleTreeCircuit::from(proof);
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+
+        // Claiming another value for the same key
+        let wrong_public_input = vec![Fp::from(1000), Fp::from(7), tree.root()];
+        let prover = MockProver::run(10, &circuit, vec![wrong_public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_non_membership() {
+        let tree = tree(16);
+        let proof = tree.proof(Fp::from(999));
+        assert!(proof.value.is_none());
+
+        let public_input = proof.public_inputs();
+        let circuit = SparseMerkleTreeCircuit::from(proof);
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+
+        // The same path does not show that a neighbouring key is absent
+        let wrong_public_input = vec![Fp::from(998), tree.root()];
+        let prover = MockProver::run(10, &circuit, vec![wrong_public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Claiming that an existing key is absent must fail, whatever siblings the prover comes up with
+    #[test]
+    fn test_non_membership_of_member() {
+        let tree = tree(16);
+        let mut proof = tree.proof(Fp::from(3));
+        proof.value = None;
+
+        let public_input = vec![Fp::from(3), tree.root()];
+        let circuit = SparseMerkleTreeCircuit::from(proof);
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Keys that do not fit in the tree cannot borrow the path of a smaller key
+    #[test]
+    fn test_key_out_of_range() {
+        let tree = tree(16);
+        let proof = tree.proof(Fp::from(999));
+        let root = proof.root();
+
+        let mut circuit = SparseMerkleTreeCircuit::from(proof);
+        let key = Fp::from(999 + (1 << 16));
+        circuit.key = Value::known(key);
+        let prover = MockProver::run(10, &circuit, vec![vec![key, root]]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    #[test]
+    fn test_deep() {
+        let tree = tree(64);
+        for key in [12345u64, 54321] {
+            let proof = tree.proof(Fp::from(key));
+            let public_input = proof.public_inputs();
+            let circuit = SparseMerkleTreeCircuit::from(proof);
+            let prover = MockProver::run(12, &circuit, vec![public_input]).unwrap();
+            prover.assert_satisfied();
+        }
+    }
+}
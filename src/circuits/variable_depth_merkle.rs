@@ -0,0 +1,230 @@
+/*
+A merkle circuit whose keys do not depend on the depth of the tree: it is laid out for a maximum depth, and the layers
+above the actual depth pass the digest through unchanged. A single verifying key then serves a tree that grows over
+time.
+*/
+
+use ff::PrimeField;
+use halo2_gadgets::poseidon::primitives::Spec;
+use halo2_proofs::{circuit::*, plonk::*};
+use halo2curves::pasta::Fp;
+
+use crate::chips::arithmetic::{ArithmeticChip, ArithmeticConfig};
+use crate::chips::merkle::{MerkleTreeChip, MerkleTreeConfig};
+use crate::trees::merkle::MerklePath;
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+#[derive(Debug, Clone)]
+pub struct VariableDepthMerkleTreeConfig<F: PrimeField = Fp> {
+    pub merkle_config: MerkleTreeConfig<F>,
+    pub arithmetic_config: ArithmeticConfig,
+}
+
+/// Same as `MerkleTreeCircuit` for any tree of depth up to `elements.len()`. Layer `i` is enabled if `i < depth`: the
+/// enable bits are boolean, never turn back on once off, and add up to the public depth, so the enabled layers are
+/// always the bottom ones. A disabled layer still gets hashed but its output is dropped.
+///
+/// The public inputs are `[leaf, root, depth]`.
+#[derive(Clone, Default)]
+pub struct VariableDepthMerkleTreeCircuit<F = Fp> {
+    pub leaf: Value<F>,
+    pub elements: Vec<Value<F>>,
+    pub indices: Vec<Value<F>>,
+    pub depth: Value<F>,
+}
+
+impl<F: PrimeField> VariableDepthMerkleTreeCircuit<F> {
+    /// The circuit proving `path` with keys for trees of up to `max_depth` layers. The layers above the path are
+    /// padded with zeros.
+    pub fn from_path(path: &MerklePath<F>, max_depth: usize) -> Self {
+        let depth = path.elements.len();
+        assert!(depth <= max_depth, "the path is deeper than {}", max_depth);
+        let padding = max_depth - depth;
+        Self {
+            leaf: Value::known(path.leaf),
+            elements: path
+                .elements
+                .iter()
+                .copied()
+                .chain(std::iter::repeat(F::ZERO).take(padding))
+                .map(Value::known)
+                .collect(),
+            indices: path
+                .indices
+                .iter()
+                .copied()
+                .chain(std::iter::repeat(0).take(padding))
+                .map(|x| Value::known(F::from(x)))
+                .collect(),
+            depth: Value::known(F::from(depth as u64)),
+        }
+    }
+}
+
+impl<F: PrimeField> Circuit<F> for VariableDepthMerkleTreeCircuit<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    type Config = VariableDepthMerkleTreeConfig<F>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            elements: vec![Value::unknown(); self.elements.len()],
+            indices: vec![Value::unknown(); self.indices.len()],
+            ..Self::default()
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let col_a = meta.advice_column();
+        let col_b = meta.advice_column();
+        let col_c = meta.advice_column();
+        let instance = meta.instance_column();
+        VariableDepthMerkleTreeConfig {
+            merkle_config: MerkleTreeChip::configure(meta, &[col_a, col_b, col_c], instance),
+            arithmetic_config: ArithmeticChip::configure(meta, [col_a, col_b, col_c]),
+        }
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let max_depth = self.elements.len();
+        let chip = MerkleTreeChip::construct(config.merkle_config);
+        let arithmetic_chip = ArithmeticChip::construct(config.arithmetic_config);
+        let zero = chip.load_constant(layouter.namespace(|| "zero"), F::ZERO)?;
+        let one = chip.load_constant(layouter.namespace(|| "one"), F::ONE)?;
+
+        let enabled_layers = self.depth.map(|depth| {
+            (0..=max_depth)
+                .position(|d| F::from(d as u64) == depth)
+                .unwrap_or(0)
+        });
+
+        let leaf_cell = chip.load_private(layouter.namespace(|| "load leaf"), self.leaf)?;
+        chip.expose_public(layouter.namespace(|| "public leaf"), &leaf_cell, 0)?;
+
+        let mut digest = leaf_cell;
+        let mut depth = zero.clone();
+        let mut previous_enable = one.clone();
+        for i in 0..max_depth {
+            let enable = chip.load_private(
+                layouter.namespace(|| format!("load enable {}", i)),
+                enabled_layers.map(|layers| F::from((i < layers) as u64)),
+            )?;
+            arithmetic_chip.assert_bool(layouter.namespace(|| format!("bool {}", i)), &enable)?;
+            // A layer can only be enabled if the one below is: enable = enable * previous_enable
+            let both = arithmetic_chip.mul_add(
+                layouter.namespace(|| format!("monotonic {}", i)),
+                &zero,
+                &enable,
+                &previous_enable,
+            )?;
+            chip.constrain_equal(
+                layouter.namespace(|| format!("monotonic {}", i)),
+                &both,
+                &enable,
+            )?;
+            depth = arithmetic_chip.mul_add(
+                layouter.namespace(|| format!("depth {}", i)),
+                &depth,
+                &enable,
+                &one,
+            )?;
+
+            let hashed = chip.merkle_prove_layer(
+                layouter.namespace(|| format!("merkle_prove_layer_{}", i)),
+                &digest,
+                &self.elements[i..i + 1],
+                self.indices[i],
+            )?;
+            digest = arithmetic_chip.select(
+                layouter.namespace(|| format!("pass through {}", i)),
+                &enable,
+                &hashed,
+                &digest,
+            )?;
+            previous_enable = enable;
+        }
+
+        chip.expose_public(layouter.namespace(|| "public root"), &digest, 1)?;
+        chip.expose_public(layouter.namespace(|| "public depth"), &depth, 2)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trees::merkle::MerkleTree;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::pasta::Fq;
+    use rand_core::OsRng;
+
+    const MAX_DEPTH: usize = 12;
+    const K: u32 = 11;
+
+    #[test]
+    fn test_variable_depth() {
+        let mut rng = OsRng;
+        let leaves = (0..20).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+
+        // The same keys serve all the depths up to the maximum
+        for depth in [5, 8, MAX_DEPTH] {
+            let tree = MerkleTree::new(depth, leaves.clone());
+            let circuit = VariableDepthMerkleTreeCircuit::from_path(&tree.path(13), MAX_DEPTH);
+            let public_input = vec![leaves[13], tree.root(), Fp::from(depth as u64)];
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            prover.assert_satisfied();
+        }
+    }
+
+    #[test]
+    fn test_variable_depth_fq() {
+        let mut rng = OsRng;
+        let leaves = (0..20).map(|_| Fq::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(8, leaves.clone());
+        let circuit = VariableDepthMerkleTreeCircuit::from_path(&tree.path(13), MAX_DEPTH);
+        let public_input = vec![leaves[13], tree.root(), Fq::from(8)];
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+    }
+
+    #[test]
+    fn test_wrong_depth() {
+        let mut rng = OsRng;
+        let leaves = (0..20).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(6, leaves.clone());
+        let circuit = VariableDepthMerkleTreeCircuit::from_path(&tree.path(13), MAX_DEPTH);
+
+        // The depth is public, and the root of a path cut short is not the root of the tree
+        for depth in [5u64, 7] {
+            let public_input = vec![leaves[13], tree.root(), Fp::from(depth)];
+            let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+            assert!(prover.verify().is_err());
+        }
+        let mut short = circuit.clone();
+        short.depth = Value::known(Fp::from(5));
+        let public_input = vec![leaves[13], tree.root(), Fp::from(5)];
+        let prover = MockProver::run(K, &short, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+
+    // Depths past the maximum cannot be claimed
+    #[test]
+    fn test_depth_out_of_range() {
+        let mut rng = OsRng;
+        let leaves = (0..20).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(MAX_DEPTH, leaves.clone());
+        let mut circuit = VariableDepthMerkleTreeCircuit::from_path(&tree.path(13), MAX_DEPTH);
+        circuit.depth = Value::known(Fp::from(MAX_DEPTH as u64 + 1));
+
+        let public_input = vec![leaves[13], tree.root(), Fp::from(MAX_DEPTH as u64 + 1)];
+        let prover = MockProver::run(K, &circuit, vec![public_input]).unwrap();
+        assert!(prover.verify().is_err());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod utils;
 pub mod chips;
 pub mod circuits;
+pub mod trees;
@@ -0,0 +1,3 @@
+pub mod indexed_merkle;
+pub mod merkle;
+pub mod sparse_merkle;
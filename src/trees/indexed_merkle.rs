@@ -0,0 +1,211 @@
+/*
+A native indexed merkle tree: a merkle tree whose leaves also form a linked list sorted by value. Every leaf points to
+the leaf holding the next larger value, so a value that falls strictly between a leaf and its successor is not in the
+tree. The first leaf is a zero sentinel that every other value hangs off.
+*/
+
+use halo2_proofs::circuit::Value;
+use halo2curves::pasta::Fp;
+
+use super::merkle::{hash_nodes, MerklePath, MerkleTree};
+use crate::circuits::indexed_merkle::IndexedMerkleTreeCircuit;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexedLeaf {
+    pub value: Fp,
+    /// Position of the leaf holding `next_value`
+    pub next_index: u64,
+    /// The next larger value in the tree, or zero if `value` is the largest one
+    pub next_value: Fp,
+}
+
+impl IndexedLeaf {
+    pub fn hash(&self) -> Fp {
+        hash_nodes(
+            hash_nodes(self.value, Fp::from(self.next_index)),
+            self.next_value,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedMerkleTree {
+    leaves: Vec<IndexedLeaf>,
+    tree: MerkleTree,
+}
+
+impl IndexedMerkleTree {
+    /// A tree of the given depth holding only the zero sentinel
+    pub fn new(depth: usize) -> Self {
+        let sentinel = IndexedLeaf::default();
+        Self {
+            leaves: vec![sentinel],
+            tree: MerkleTree::new(depth, vec![sentinel.hash()]),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn root(&self) -> Fp {
+        self.tree.root()
+    }
+
+    pub fn leaves(&self) -> &[IndexedLeaf] {
+        &self.leaves
+    }
+
+    /// Whether `value` is in the tree. Zero always is, as the sentinel.
+    pub fn contains(&self, value: Fp) -> bool {
+        self.leaves.iter().any(|leaf| leaf.value == value)
+    }
+
+    /// Insert `value` in the next free leaf and link it after its low leaf. Values can be any non-zero field element
+    /// that is not in the tree yet, and are ordered as the integers in [0, p) they stand for.
+    pub fn insert(&mut self, value: Fp) {
+        assert!(!self.contains(value), "value is already in the tree");
+        let index = self.leaves.len();
+        assert!(index < 1 << self.depth(), "the tree is full");
+
+        let low_index = self.low_leaf_index(value);
+        let low_leaf = self.leaves[low_index];
+        let leaf = IndexedLeaf {
+            value,
+            next_index: low_leaf.next_index,
+            next_value: low_leaf.next_value,
+        };
+        self.leaves[low_index] = IndexedLeaf {
+            next_index: index as u64,
+            next_value: value,
+            ..low_leaf
+        };
+        self.leaves.push(leaf);
+
+        self.tree.set(low_index, self.leaves[low_index].hash());
+        self.tree.set(index, leaf.hash());
+    }
+
+    /// A proof that `value` is not in the tree, or `None` if it is
+    pub fn non_membership_proof(&self, value: Fp) -> Option<IndexedMerkleProof> {
+        if self.contains(value) {
+            return None;
+        }
+        let low_index = self.low_leaf_index(value);
+        Some(IndexedMerkleProof {
+            value,
+            low_leaf: self.leaves[low_index],
+            path: self.tree.path(low_index),
+        })
+    }
+
+    // The leaf holding the largest value below `value`. The sentinel guarantees there is one.
+    fn low_leaf_index(&self, value: Fp) -> usize {
+        self.leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| leaf.value < value)
+            .max_by_key(|(_, leaf)| leaf.value)
+            .map(|(index, _)| index)
+            .unwrap()
+    }
+}
+
+/// Proof that `value` is not in the tree: `value` lies between `low_leaf` and its successor, and `path` shows that
+/// `low_leaf` is in the tree.
+#[derive(Debug, Clone)]
+pub struct IndexedMerkleProof {
+    pub value: Fp,
+    pub low_leaf: IndexedLeaf,
+    pub path: MerklePath,
+}
+
+impl IndexedMerkleProof {
+    /// The root this proof leads to
+    pub fn root(&self) -> Fp {
+        self.path.root()
+    }
+
+    /// The public inputs of the matching `IndexedMerkleTreeCircuit`
+    pub fn public_inputs(&self) -> Vec<Fp> {
+        vec![self.value, self.root()]
+    }
+}
+
+impl From<IndexedMerkleProof> for IndexedMerkleTreeCircuit {
+    fn from(proof: IndexedMerkleProof) -> Self {
+        IndexedMerkleTreeCircuit {
+            value: Value::known(proof.value),
+            low_value: Value::known(proof.low_leaf.value),
+            low_next_index: Value::known(Fp::from(proof.low_leaf.next_index)),
+            low_next_value: Value::known(proof.low_leaf.next_value),
+            elements: proof.path.elements.into_iter().map(Value::known).collect(),
+            indices: proof
+                .path
+                .indices
+                .into_iter()
+                .map(|x| Value::known(Fp::from(x)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut tree = IndexedMerkleTree::new(8);
+        for value in [30u64, 10, 20] {
+            tree.insert(Fp::from(value));
+        }
+
+        // The leaves stay in insertion order while their links follow the values
+        let links = tree
+            .leaves()
+            .iter()
+            .map(|leaf| (leaf.next_index, leaf.next_value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            vec![
+                (2, Fp::from(10)),
+                (0, Fp::zero()),
+                (3, Fp::from(20)),
+                (1, Fp::from(30)),
+            ]
+        );
+
+        let leaves = tree.leaves().iter().map(IndexedLeaf::hash).collect();
+        assert_eq!(tree.root(), MerkleTree::new(8, leaves).root());
+    }
+
+    #[test]
+    fn test_non_membership_proof() {
+        let mut tree = IndexedMerkleTree::new(8);
+        for value in [30u64, 10, 20] {
+            tree.insert(Fp::from(value));
+        }
+
+        assert!(tree.non_membership_proof(Fp::from(20)).is_none());
+        assert!(tree.non_membership_proof(Fp::zero()).is_none());
+
+        let proof = tree.non_membership_proof(Fp::from(25)).unwrap();
+        assert_eq!(proof.low_leaf.value, Fp::from(20));
+        assert_eq!(proof.low_leaf.next_value, Fp::from(30));
+        assert_eq!(proof.root(), tree.root());
+
+        let proof = tree.non_membership_proof(Fp::from(31)).unwrap();
+        assert_eq!(proof.low_leaf.value, Fp::from(30));
+        assert_eq!(proof.low_leaf.next_value, Fp::zero());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_insert_twice() {
+        let mut tree = IndexedMerkleTree::new(8);
+        tree.insert(Fp::from(10));
+        tree.insert(Fp::from(10));
+    }
+}
@@ -0,0 +1,645 @@
+/*
+A native merkle tree that hashes its nodes exactly like `MerkleTreeChip` does, so that its roots and authentication
+paths can be fed straight into `MerkleTreeCircuit`. Trees work over either field of the Pasta cycle, the batch appends
+are only implemented over `Fp`.
+*/
+
+use ff::PrimeField;
+use halo2_gadgets::poseidon::primitives::{self as poseidon, ConstantLength, Spec};
+use halo2_proofs::circuit::Value;
+use halo2curves::pasta::Fp;
+use std::marker::PhantomData;
+
+use crate::circuits::merkle::{
+    compute_merkle_root, MerkleTreeCircuit, MultiMerkleTreeCircuit, PositionalMerkleTreeCircuit,
+    PrivateMerkleTreeCircuit,
+};
+use crate::circuits::batch_append::BatchAppendCircuit;
+use crate::circuits::merkle_multiproof::MerkleMultiproofCircuit;
+use crate::circuits::merkle_update::MerkleUpdateCircuit;
+use crate::utils::p128pow5t3::P128Pow5T3 as OrchardNullifier;
+
+/// Hash two sibling nodes into their parent node
+pub fn hash_nodes<F: PrimeField>(left: F, right: F) -> F
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    poseidon::Hash::<_, OrchardNullifier, ConstantLength<2>, 3, 2>::init().hash([left, right])
+}
+
+/// The root of a subtree of `height` whose leaves are all empty
+pub fn empty_root(height: usize) -> Fp {
+    (0..height).fold(Fp::zero(), |node, _| hash_nodes(node, node))
+}
+
+/// The root of a subtree of `leaves.len()` leaves, which has to be a power of two
+pub fn subtree_root(leaves: &[Fp]) -> Fp {
+    assert!(leaves.len().is_power_of_two(), "{} leaves do not form a subtree", leaves.len());
+    let mut layer = leaves.to_vec();
+    while layer.len() > 1 {
+        layer = layer.chunks(2).map(|pair| hash_nodes(pair[0], pair[1])).collect();
+    }
+    layer[0]
+}
+
+/// The public commitment to a batch of appended leaves
+pub fn batch_commitment<const N: usize>(leaves: [Fp; N]) -> Fp {
+    poseidon::Hash::<_, OrchardNullifier, ConstantLength<N>, 3, 2>::init().hash(leaves)
+}
+
+/// A binary merkle tree of fixed depth over `F`. Leaves that were never set are zero.
+#[derive(Debug, Clone)]
+pub struct MerkleTree<F = Fp> {
+    depth: usize,
+    // `layers[0]` holds the leaves and `layers[depth]` the root. A layer only stores the nodes up to the last one that
+    // has a set leaf below it: everything to their right is an empty subtree whose hash lives in `empty_nodes`.
+    layers: Vec<Vec<F>>,
+    // `empty_nodes[i]` is the root of an empty subtree of height `i`
+    empty_nodes: Vec<F>,
+}
+
+impl<F: PrimeField> MerkleTree<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    /// Build a tree of `depth` layers whose leftmost leaves are `leaves`.
+    ///
+    /// Only the non-empty part of the tree gets hashed, so deep trees with few leaves are cheap to build.
+    pub fn new(depth: usize, leaves: Vec<F>) -> Self {
+        assert!(depth > 0, "a merkle tree needs at least one layer");
+        assert!(
+            depth < usize::BITS as usize && leaves.len() <= 1 << depth,
+            "{} leaves do not fit in a tree of depth {}",
+            leaves.len(),
+            depth
+        );
+
+        let mut empty_nodes = vec![F::ZERO];
+        for i in 0..depth {
+            empty_nodes.push(hash_nodes(empty_nodes[i], empty_nodes[i]));
+        }
+
+        let mut layers = vec![leaves];
+        for i in 0..depth {
+            let layer = layers[i]
+                .chunks(2)
+                .map(|pair| hash_nodes(pair[0], *pair.get(1).unwrap_or(&empty_nodes[i])))
+                .collect();
+            layers.push(layer);
+        }
+
+        Self {
+            depth,
+            layers,
+            empty_nodes,
+        }
+    }
+
+    /// Build the smallest tree (of depth at least 1) that fits all of `leaves`
+    pub fn from_leaves(leaves: Vec<F>) -> Self {
+        let depth = (usize::BITS - (leaves.len().max(2) - 1).leading_zeros()) as usize;
+        Self::new(depth, leaves)
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn root(&self) -> F {
+        self.node(self.depth, 0)
+    }
+
+    /// The leaves up to the last one that was set
+    pub fn leaves(&self) -> &[F] {
+        &self.layers[0]
+    }
+
+    pub fn leaf(&self, index: usize) -> F {
+        assert!(index < 1 << self.depth, "leaf index {} out of range", index);
+        self.node(0, index)
+    }
+
+    /// Replace the leaf at `index` and rehash its path up to the root
+    pub fn set(&mut self, index: usize, leaf: F) {
+        assert!(index < 1 << self.depth, "leaf index {} out of range", index);
+        let mut node = leaf;
+        for layer in 0..self.depth {
+            let i = index >> layer;
+            if self.layers[layer].len() <= i {
+                self.layers[layer].resize(i + 1, self.empty_nodes[layer]);
+            }
+            self.layers[layer][i] = node;
+
+            let sibling = self.node(layer, i ^ 1);
+            node = if i & 1 == 0 {
+                hash_nodes(node, sibling)
+            } else {
+                hash_nodes(sibling, node)
+            };
+        }
+        self.layers[self.depth] = vec![node];
+    }
+
+    /// Replace the leaf at `index` like `set` does, and return the witness proving the transition between the old and
+    /// the new root
+    pub fn update(&mut self, index: usize, leaf: F) -> MerkleUpdate<F> {
+        let path = self.path(index);
+        self.set(index, leaf);
+        MerkleUpdate {
+            old_leaf: path.leaf,
+            new_leaf: leaf,
+            elements: path.elements,
+            indices: path.indices,
+        }
+    }
+
+    /// The multiproof of the leaves at `indices`. Only the siblings that cannot be recomputed from the proven leaves
+    /// are part of it, so leaves close to each other share most of their paths.
+    pub fn multiproof(&self, indices: &[usize]) -> MerkleMultiproof<F> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        assert!(!indices.is_empty(), "there has to be at least one leaf to prove");
+        assert!(
+            indices.iter().all(|index| *index < 1 << self.depth),
+            "leaf index out of range"
+        );
+
+        let leaves = indices.iter().map(|index| self.node(0, *index)).collect();
+        let mut siblings = vec![];
+        let mut layer_indices = indices.clone();
+        for layer in 0..self.depth {
+            let mut i = 0;
+            while i < layer_indices.len() {
+                let index = layer_indices[i];
+                if index & 1 == 0 && layer_indices.get(i + 1) == Some(&(index + 1)) {
+                    i += 2;
+                } else {
+                    siblings.push(self.node(layer, index ^ 1));
+                    i += 1;
+                }
+            }
+            layer_indices = layer_indices.iter().map(|index| index >> 1).collect();
+            layer_indices.dedup();
+        }
+
+        MerkleMultiproof {
+            depth: self.depth,
+            indices: indices.into_iter().map(|index| index as u64).collect(),
+            leaves,
+            siblings,
+        }
+    }
+
+    /// The authentication path of the leaf at `index`
+    pub fn path(&self, index: usize) -> MerklePath<F> {
+        assert!(index < 1 << self.depth, "leaf index {} out of range", index);
+        MerklePath {
+            leaf: self.node(0, index),
+            elements: (0..self.depth)
+                .map(|i| self.node(i, (index >> i) ^ 1))
+                .collect(),
+            indices: (0..self.depth).map(|i| ((index >> i) & 1) as u64).collect(),
+        }
+    }
+
+    fn node(&self, layer: usize, index: usize) -> F {
+        self.layers[layer]
+            .get(index)
+            .copied()
+            .unwrap_or(self.empty_nodes[layer])
+    }
+}
+
+impl MerkleTree<Fp> {
+    /// Append `leaves` to the tree, and return the witness proving the transition between the old and the new root.
+    ///
+    /// The batch fills the first free subtree of `N` leaves, `N` being a power of two. Panics if the leaves set so far
+    /// do not end on a batch boundary, as the circuit can only replace a whole empty subtree.
+    pub fn append_batch<const N: usize>(&mut self, leaves: [Fp; N]) -> BatchAppend<N> {
+        assert!(N.is_power_of_two(), "batches of {} leaves do not form a subtree", N);
+        let height = N.trailing_zeros() as usize;
+        assert!(height <= self.depth, "batches of {} leaves do not fit in the tree", N);
+        let start_index = self.leaves().len();
+        assert!(
+            start_index % N == 0,
+            "leaf {} does not start a batch of {} leaves",
+            start_index,
+            N
+        );
+        assert!(start_index < 1 << self.depth, "the tree is full");
+
+        let path = self.path(start_index);
+        for (i, leaf) in leaves.iter().enumerate() {
+            self.set(start_index + i, *leaf);
+        }
+        BatchAppend {
+            start_index: start_index as u64,
+            leaves,
+            elements: path.elements[height..].to_vec(),
+        }
+    }
+}
+
+/// The authentication path of a single leaf, laid out the way `compute_merkle_root` and `MerkleTreeCircuit` expect
+/// it: `elements` are the siblings from the leaf upwards, and `indices` tell whether the running digest is the left
+/// (0) or the right (1) input of each layer.
+#[derive(Debug, Clone)]
+pub struct MerklePath<F = Fp> {
+    pub leaf: F,
+    pub elements: Vec<F>,
+    pub indices: Vec<u64>,
+}
+
+impl<F: PrimeField> MerklePath<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    /// The root this path leads to
+    pub fn root(&self) -> F {
+        compute_merkle_root(&self.leaf, &self.elements, &self.indices)
+    }
+
+    /// The position of the leaf in the tree
+    pub fn position(&self) -> u64 {
+        self.indices
+            .iter()
+            .rev()
+            .fold(0, |position, index| position << 1 | index)
+    }
+}
+
+/// The replacement of a single leaf. The siblings are the same before and after the update, only the leaf changes.
+#[derive(Debug, Clone)]
+pub struct MerkleUpdate<F = Fp> {
+    pub old_leaf: F,
+    pub new_leaf: F,
+    pub elements: Vec<F>,
+    pub indices: Vec<u64>,
+}
+
+impl<F: PrimeField> MerkleUpdate<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    pub fn old_root(&self) -> F {
+        compute_merkle_root(&self.old_leaf, &self.elements, &self.indices)
+    }
+
+    pub fn new_root(&self) -> F {
+        compute_merkle_root(&self.new_leaf, &self.elements, &self.indices)
+    }
+}
+
+/// The append of `N` leaves to an empty subtree starting at `start_index`. `elements` are the siblings from the root of
+/// that subtree upwards, which stay the same before and after the append.
+#[derive(Debug, Clone)]
+pub struct BatchAppend<const N: usize> {
+    pub start_index: u64,
+    pub leaves: [Fp; N],
+    pub elements: Vec<Fp>,
+}
+
+impl<const N: usize> BatchAppend<N> {
+    fn indices(&self) -> Vec<u64> {
+        let height = N.trailing_zeros() as usize;
+        (0..self.elements.len())
+            .map(|i| (self.start_index >> (height + i)) & 1)
+            .collect()
+    }
+
+    pub fn old_root(&self) -> Fp {
+        let empty = empty_root(N.trailing_zeros() as usize);
+        compute_merkle_root(&empty, &self.elements, &self.indices())
+    }
+
+    pub fn new_root(&self) -> Fp {
+        compute_merkle_root(&subtree_root(&self.leaves), &self.elements, &self.indices())
+    }
+
+    pub fn commitment(&self) -> Fp {
+        batch_commitment(self.leaves)
+    }
+
+    /// The public inputs of the `BatchAppendCircuit` proving this append
+    pub fn public_inputs(&self) -> Vec<Fp> {
+        vec![
+            self.old_root(),
+            self.new_root(),
+            Fp::from(self.start_index),
+            self.commitment(),
+        ]
+    }
+}
+
+/// The authentication of several leaves at once. `indices` are sorted and `leaves[i]` sits at `indices[i]`. Walking up
+/// the tree a layer at a time and through the nodes of a layer from left to right, two nodes that are siblings of each
+/// other are hashed together, and every other node takes the next of `siblings`.
+#[derive(Debug, Clone)]
+pub struct MerkleMultiproof<F = Fp> {
+    pub depth: usize,
+    pub indices: Vec<u64>,
+    pub leaves: Vec<F>,
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> MerkleMultiproof<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    /// The root this multiproof leads to. Panics if an index is not in the tree.
+    pub fn root(&self) -> F {
+        assert!(
+            self.indices.iter().all(|index| *index < 1 << self.depth),
+            "indices have to be in the tree"
+        );
+        let mut siblings = self.siblings.iter();
+        let mut nodes = self
+            .indices
+            .iter()
+            .copied()
+            .zip(self.leaves.iter().copied())
+            .collect::<Vec<_>>();
+        for _ in 0..self.depth {
+            let mut next = vec![];
+            let mut i = 0;
+            while i < nodes.len() {
+                let (index, node) = nodes[i];
+                let digest = if index & 1 == 0 && nodes.get(i + 1).map(|x| x.0) == Some(index + 1) {
+                    i += 1;
+                    hash_nodes(node, nodes[i].1)
+                } else {
+                    let sibling = *siblings.next().expect("missing sibling");
+                    if index & 1 == 0 {
+                        hash_nodes(node, sibling)
+                    } else {
+                        hash_nodes(sibling, node)
+                    }
+                };
+                next.push((index >> 1, digest));
+                i += 1;
+            }
+            nodes = next;
+        }
+        assert!(siblings.next().is_none(), "too many siblings");
+        assert_eq!(nodes.len(), 1, "the leaves do not lead to a single root");
+        nodes[0].1
+    }
+
+    /// The public inputs of the `MerkleMultiproofCircuit` checking this multiproof against `root`
+    pub fn public_inputs(&self, root: F) -> Vec<F> {
+        let mut public_inputs = self.leaves.clone();
+        public_inputs.push(root);
+        public_inputs
+    }
+}
+
+impl<F: PrimeField> From<MerklePath<F>> for MerkleTreeCircuit<F> {
+    fn from(path: MerklePath<F>) -> Self {
+        MerkleTreeCircuit {
+            leaf: Value::known(path.leaf),
+            elements: path.elements.into_iter().map(Value::known).collect(),
+            indices: path
+                .indices
+                .into_iter()
+                .map(|x| Value::known(F::from(x)))
+                .collect(),
+            _spec: PhantomData,
+        }
+    }
+}
+
+/// The position of the leaf stays private, set `public_position` to expose it
+impl<F: PrimeField> From<MerklePath<F>> for PositionalMerkleTreeCircuit<F>
+where
+    OrchardNullifier: Spec<F, 3, 2>,
+{
+    fn from(path: MerklePath<F>) -> Self {
+        PositionalMerkleTreeCircuit {
+            leaf: Value::known(path.leaf),
+            position: Value::known(F::from(path.position())),
+            elements: path.elements.into_iter().map(Value::known).collect(),
+            public_position: false,
+            _spec: PhantomData,
+        }
+    }
+}
+
+impl<F: PrimeField> From<MerklePath<F>> for PrivateMerkleTreeCircuit<F> {
+    fn from(path: MerklePath<F>) -> Self {
+        let circuit = MerkleTreeCircuit::from(path);
+        PrivateMerkleTreeCircuit {
+            leaf: circuit.leaf,
+            elements: circuit.elements,
+            indices: circuit.indices,
+        }
+    }
+}
+
+impl<F: PrimeField> From<Vec<MerklePath<F>>> for MultiMerkleTreeCircuit<F> {
+    fn from(paths: Vec<MerklePath<F>>) -> Self {
+        let circuits = paths
+            .into_iter()
+            .map(MerkleTreeCircuit::from)
+            .collect::<Vec<_>>();
+        MultiMerkleTreeCircuit {
+            leaves: circuits.iter().map(|circuit| circuit.leaf).collect(),
+            elements: circuits.iter().map(|circuit| circuit.elements.clone()).collect(),
+            indices: circuits.iter().map(|circuit| circuit.indices.clone()).collect(),
+        }
+    }
+}
+
+impl From<MerkleUpdate> for MerkleUpdateCircuit {
+    fn from(update: MerkleUpdate) -> Self {
+        MerkleUpdateCircuit {
+            old_leaf: Value::known(update.old_leaf),
+            new_leaf: Value::known(update.new_leaf),
+            elements: update.elements.into_iter().map(Value::known).collect(),
+            indices: update
+                .indices
+                .into_iter()
+                .map(|x| Value::known(Fp::from(x)))
+                .collect(),
+        }
+    }
+}
+
+impl<const N: usize> From<BatchAppend<N>> for BatchAppendCircuit<N> {
+    fn from(batch: BatchAppend<N>) -> Self {
+        BatchAppendCircuit {
+            start_index: Value::known(Fp::from(batch.start_index)),
+            leaves: batch.leaves.map(Value::known),
+            elements: batch.elements.into_iter().map(Value::known).collect(),
+        }
+    }
+}
+
+impl From<MerkleMultiproof> for MerkleMultiproofCircuit {
+    fn from(multiproof: MerkleMultiproof) -> Self {
+        MerkleMultiproofCircuit {
+            depth: multiproof.depth,
+            indices: multiproof.indices,
+            leaves: multiproof.leaves.into_iter().map(Value::known).collect(),
+            siblings: multiproof.siblings.into_iter().map(Value::known).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::Field;
+    use halo2_proofs::dev::MockProver;
+    use halo2curves::pasta::Fq;
+    use rand_core::OsRng;
+
+    #[test]
+    fn test_root() {
+        let leaves = (1..=4u64).map(Fp::from).collect::<Vec<_>>();
+        let tree = MerkleTree::from_leaves(leaves.clone());
+
+        let expected = hash_nodes(
+            hash_nodes(leaves[0], leaves[1]),
+            hash_nodes(leaves[2], leaves[3]),
+        );
+        assert_eq!(tree.depth(), 2);
+        assert_eq!(tree.root(), expected);
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut rng = OsRng;
+        let leaves = (0..5).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(4, leaves.clone());
+
+        // Both the set leaves and the empty ones lead to the same root
+        for index in 0..16 {
+            let path = tree.path(index);
+            assert_eq!(path.leaf, leaves.get(index).copied().unwrap_or(Fp::zero()));
+            assert_eq!(path.root(), tree.root());
+        }
+    }
+
+    #[test]
+    fn test_positional_circuit_from_path() {
+        let mut rng = OsRng;
+        let leaves = (0..100).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(12, leaves);
+
+        let path = tree.path(77);
+        assert_eq!(path.position(), 77);
+        let public_input = vec![path.leaf, tree.root(), Fp::from(77)];
+        let circuit: PositionalMerkleTreeCircuit = PositionalMerkleTreeCircuit {
+            public_position: true,
+            ..PositionalMerkleTreeCircuit::from(path)
+        };
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+    }
+
+    #[test]
+    fn test_set() {
+        let mut rng = OsRng;
+        let leaves = (0..6).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(5, leaves[..3].to_vec());
+
+        // Setting leaves one by one ends up in the same tree as building it in one go, also past the last set leaf
+        tree.set(2, leaves[2]);
+        tree.set(4, leaves[4]);
+        tree.set(3, leaves[3]);
+        tree.set(5, leaves[5]);
+        assert_eq!(tree.root(), MerkleTree::new(5, leaves.clone()).root());
+        for index in 0..32 {
+            assert_eq!(tree.path(index).root(), tree.root());
+        }
+    }
+
+    #[test]
+    fn test_append_batch() {
+        let mut rng = OsRng;
+        let leaves = (0..12).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(6, leaves[..4].to_vec());
+
+        let batch = tree.append_batch([leaves[4], leaves[5], leaves[6], leaves[7]]);
+        assert_eq!(batch.start_index, 4);
+        let batch = tree.append_batch([leaves[8], leaves[9], leaves[10], leaves[11]]);
+        assert_eq!(batch.start_index, 8);
+        assert_eq!(batch.new_root(), tree.root());
+        assert_eq!(tree.root(), MerkleTree::new(6, leaves).root());
+    }
+
+    // A batch cannot start in the middle of the half-filled batch at 4..8
+    #[test]
+    #[should_panic]
+    fn test_append_unaligned_batch() {
+        let leaves = (0..9).map(|_| Fp::random(OsRng)).collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(6, leaves[..5].to_vec());
+        tree.append_batch([leaves[5], leaves[6], leaves[7], leaves[8]]);
+    }
+
+    #[test]
+    fn test_multiproof() {
+        let mut rng = OsRng;
+        let leaves = (0..40).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(8, leaves.clone());
+
+        // Four adjacent leaves only need the siblings of their common subtree
+        let multiproof = tree.multiproof(&[5, 4, 7, 6]);
+        assert_eq!(multiproof.indices, vec![4, 5, 6, 7]);
+        assert_eq!(multiproof.leaves, leaves[4..8].to_vec());
+        assert_eq!(multiproof.siblings.len(), 6);
+        assert_eq!(multiproof.root(), tree.root());
+
+        // Leaves further apart still share the top of their paths: 18 siblings instead of 4 * 8
+        let multiproof = tree.multiproof(&[0, 3, 39, 200]);
+        assert_eq!(multiproof.siblings.len(), 18);
+        assert_eq!(multiproof.root(), tree.root());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_multiproof_index_out_of_range() {
+        let leaves = (0..40).map(|_| Fp::random(OsRng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(8, leaves);
+        let mut multiproof = tree.multiproof(&[4, 5, 6, 7]);
+        multiproof.indices[3] += 1 << 8;
+        multiproof.root();
+    }
+
+    #[test]
+    fn test_circuit_from_path() {
+        let mut rng = OsRng;
+        let leaves = (0..100).map(|_| Fp::random(&mut rng)).collect::<Vec<_>>();
+        let tree = MerkleTree::new(16, leaves);
+
+        let path = tree.path(42);
+        let public_input = vec![path.leaf, tree.root()];
+        let circuit = MerkleTreeCircuit::from(path);
+
+        let prover = MockProver::run(
+            10,
+            &circuit,
+            vec![public_input.clone()],
+        )
+        .unwrap();
+        prover.assert_satisfied();
+    }
+
+    #[test]
+    fn test_fq() {
+        let mut rng = OsRng;
+        let leaves = (0..100).map(|_| Fq::random(&mut rng)).collect::<Vec<_>>();
+        let mut tree = MerkleTree::new(16, leaves);
+
+        let update = tree.update(42, Fq::random(&mut rng));
+        assert_eq!(update.new_root(), tree.root());
+
+        let path = tree.path(42);
+        assert_eq!(path.root(), tree.root());
+        let public_input = vec![path.leaf, tree.root()];
+        let circuit = MerkleTreeCircuit::from(path);
+        let prover = MockProver::run(10, &circuit, vec![public_input]).unwrap();
+        prover.assert_satisfied();
+    }
+}
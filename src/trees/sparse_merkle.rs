@@ -0,0 +1,226 @@
+/*
+A native sparse merkle tree keyed by field elements. The path of a key is given by its bits, with the least significant
+bit deciding the direction at the leaf layer. Subtrees that hold no keys are never stored: their hashes are precomputed
+once per layer.
+*/
+
+use ff::PrimeField;
+use halo2_proofs::circuit::Value;
+use halo2curves::pasta::Fp;
+use std::collections::HashMap;
+
+use super::merkle::hash_nodes;
+use crate::circuits::merkle::compute_merkle_root;
+use crate::circuits::sparse_merkle::SparseMerkleTreeCircuit;
+
+/// Largest supported depth. Keys must have fewer bits than the field so that their in-circuit decomposition is unique.
+pub const MAX_DEPTH: usize = Fp::NUM_BITS as usize - 1;
+
+/// The leaf stored in the slot of `key` when it maps to `value`. Empty slots hold zero.
+pub fn leaf_hash(key: Fp, value: Fp) -> Fp {
+    hash_nodes(key, value)
+}
+
+/// The first `depth` bits of `key`, least significant first. Panics if `key` does not fit in `depth` bits.
+pub fn key_bits(key: Fp, depth: usize) -> Vec<bool> {
+    let repr = key.to_repr();
+    let bits = (0..Fp::NUM_BITS as usize)
+        .map(|i| (repr.as_ref()[i / 8] >> (i % 8)) & 1 == 1)
+        .collect::<Vec<_>>();
+    assert!(
+        bits[depth..].iter().all(|bit| !bit),
+        "key does not fit in a tree of depth {}",
+        depth
+    );
+    bits[..depth].to_vec()
+}
+
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree {
+    depth: usize,
+    // Values are indexed by the bits of their key
+    values: HashMap<Vec<bool>, Fp>,
+    // Nodes are indexed by their layer and the key bits leading from them up to the root. Only the nodes that have at
+    // least one key below them are stored.
+    nodes: HashMap<(usize, Vec<bool>), Fp>,
+    // `empty_nodes[i]` is the root of an empty subtree of height `i`
+    empty_nodes: Vec<Fp>,
+}
+
+impl SparseMerkleTree {
+    /// An empty tree of the given depth. Keys have to be smaller than `2^depth`.
+    pub fn new(depth: usize) -> Self {
+        assert!(
+            depth > 0 && depth <= MAX_DEPTH,
+            "unsupported sparse merkle tree depth {}",
+            depth
+        );
+
+        let mut empty_nodes = vec![Fp::zero()];
+        for i in 0..depth {
+            empty_nodes.push(hash_nodes(empty_nodes[i], empty_nodes[i]));
+        }
+
+        Self {
+            depth,
+            values: HashMap::new(),
+            nodes: HashMap::new(),
+            empty_nodes,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn root(&self) -> Fp {
+        self.node(self.depth, &[])
+    }
+
+    pub fn get(&self, key: Fp) -> Option<Fp> {
+        self.values.get(&key_bits(key, self.depth)).copied()
+    }
+
+    /// Map `key` to `value`, returning the value it previously mapped to
+    pub fn insert(&mut self, key: Fp, value: Fp) -> Option<Fp> {
+        let bits = key_bits(key, self.depth);
+        self.set_leaf(&bits, leaf_hash(key, value));
+        self.values.insert(bits, value)
+    }
+
+    /// Empty the slot of `key`, returning the value it used to map to
+    pub fn remove(&mut self, key: Fp) -> Option<Fp> {
+        let bits = key_bits(key, self.depth);
+        self.set_leaf(&bits, Fp::zero());
+        self.values.remove(&bits)
+    }
+
+    /// A membership proof if `key` is in the tree, and a non-membership proof otherwise
+    pub fn proof(&self, key: Fp) -> SparseMerkleProof {
+        let bits = key_bits(key, self.depth);
+        SparseMerkleProof {
+            key,
+            value: self.values.get(&bits).copied(),
+            elements: (0..self.depth).map(|i| self.sibling(i, &bits)).collect(),
+        }
+    }
+
+    fn set_leaf(&mut self, bits: &[bool], leaf: Fp) {
+        let mut digest = leaf;
+        for layer in 0..self.depth {
+            self.set_node(layer, &bits[layer..], digest);
+            let sibling = self.sibling(layer, bits);
+            digest = if bits[layer] {
+                hash_nodes(sibling, digest)
+            } else {
+                hash_nodes(digest, sibling)
+            };
+        }
+        self.set_node(self.depth, &[], digest);
+    }
+
+    fn set_node(&mut self, layer: usize, position: &[bool], node: Fp) {
+        if node == self.empty_nodes[layer] {
+            self.nodes.remove(&(layer, position.to_vec()));
+        } else {
+            self.nodes.insert((layer, position.to_vec()), node);
+        }
+    }
+
+    fn node(&self, layer: usize, position: &[bool]) -> Fp {
+        self.nodes
+            .get(&(layer, position.to_vec()))
+            .copied()
+            .unwrap_or(self.empty_nodes[layer])
+    }
+
+    fn sibling(&self, layer: usize, bits: &[bool]) -> Fp {
+        let mut position = bits[layer..].to_vec();
+        position[0] = !position[0];
+        self.node(layer, &position)
+    }
+}
+
+/// Proof that the slot of `key` holds `value`, or that it is empty if `value` is `None`
+#[derive(Debug, Clone)]
+pub struct SparseMerkleProof {
+    pub key: Fp,
+    pub value: Option<Fp>,
+    pub elements: Vec<Fp>,
+}
+
+impl SparseMerkleProof {
+    /// The root this proof leads to
+    pub fn root(&self) -> Fp {
+        let leaf = match self.value {
+            Some(value) => leaf_hash(self.key, value),
+            None => Fp::zero(),
+        };
+        let indices = key_bits(self.key, self.elements.len())
+            .into_iter()
+            .map(u64::from)
+            .collect();
+        compute_merkle_root(&leaf, &self.elements, &indices)
+    }
+
+    /// The public inputs of the matching `SparseMerkleTreeCircuit`
+    pub fn public_inputs(&self) -> Vec<Fp> {
+        match self.value {
+            Some(value) => vec![self.key, value, self.root()],
+            None => vec![self.key, self.root()],
+        }
+    }
+}
+
+impl From<SparseMerkleProof> for SparseMerkleTreeCircuit {
+    fn from(proof: SparseMerkleProof) -> Self {
+        SparseMerkleTreeCircuit {
+            key: Value::known(proof.key),
+            value: proof.value.map(Value::known),
+            elements: proof.elements.into_iter().map(Value::known).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::Field;
+    use rand_core::OsRng;
+
+    #[test]
+    fn test_insert_and_remove() {
+        let mut rng = OsRng;
+        let mut tree = SparseMerkleTree::new(32);
+        let empty_root = tree.root();
+
+        let keys = [3u64, 4, 1 << 20, (1 << 32) - 1].map(Fp::from);
+        let values = keys.map(|_| Fp::random(&mut rng));
+        for (key, value) in keys.iter().zip(values.iter()) {
+            assert_eq!(tree.insert(*key, *value), None);
+        }
+        assert_ne!(tree.root(), empty_root);
+
+        for (key, value) in keys.iter().zip(values.iter()) {
+            let proof = tree.proof(*key);
+            assert_eq!(proof.value, Some(*value));
+            assert_eq!(proof.root(), tree.root());
+        }
+        let proof = tree.proof(Fp::from(5));
+        assert_eq!(proof.value, None);
+        assert_eq!(proof.root(), tree.root());
+
+        // Removing everything brings back the empty tree
+        for (key, value) in keys.iter().zip(values.iter()) {
+            assert_eq!(tree.remove(*key), Some(*value));
+        }
+        assert_eq!(tree.root(), empty_root);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_key_too_large() {
+        let mut tree = SparseMerkleTree::new(8);
+        tree.insert(Fp::from(256), Fp::one());
+    }
+}
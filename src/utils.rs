@@ -1,3 +1,10 @@
-pub mod fp;
-pub mod fq;
+#[cfg(test)]
+mod fp;
+#[cfg(test)]
+mod fq;
+pub mod grain;
+pub mod p128pow5t12;
 pub mod p128pow5t3;
+pub mod p128pow5t5;
+pub mod p128pow5t9;
+pub mod poseidon;
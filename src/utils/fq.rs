@@ -1,4 +1,4 @@
-//! Constants for using Poseidon with the Vesta field.
+//! Constants for using Poseidon with the Vesta field, kept to test `utils::grain` against.
 //!
 //! The constants can be reproduced by running the following Sage script from
 //! [this repository](https://github.com/daira/pasta-hadeshash):
@@ -6,6 +6,8 @@
 //! ```text
 //! sage generate_parameters_grain.sage 1 0 255 3 8 56 0x40000000000000000000000000000000224698fc0994a8dd8c46eb2100000001
 //! ```
+//!
+//! `utils::grain` reproduces them as well, with `generate_constants::<Fq, 3>(8, 56)`.
 use halo2curves::pasta::vesta;
 
 // Number of round constants: 192
@@ -0,0 +1,696 @@
+//! The Grain LFSR used by the Poseidon reference implementation to derive round constants and MDS matrices.
+//!
+//! This follows `generate_parameters_grain.sage` from [the Poseidon
+//! repository](https://extgit.iaik.tugraz.at/krypto/hadeshash), so that the parameters for any prime field, width and
+//! number of rounds can be derived in Rust instead of being vendored as tables. The Pasta tables `fp` and `fq` are only
+//! kept to test against. Like the script, it keeps sampling MDS matrices until one passes the checks against infinitely
+//! long subspace trails.
+
+use std::marker::PhantomData;
+
+use ff::{FromUniformBytes, PrimeField};
+use halo2_gadgets::poseidon::primitives::{Mds, Spec};
+
+const STATE: usize = 80;
+
+/// The S-box the parameters are generated for. It is part of the initial state of the LFSR.
+#[derive(Debug, Clone, Copy)]
+pub enum SboxType {
+    /// $x^\alpha$
+    Pow,
+    /// $x^{-1}$
+    Inv,
+}
+
+impl SboxType {
+    fn tag(&self) -> u16 {
+        match self {
+            SboxType::Pow => 0,
+            SboxType::Inv => 1,
+        }
+    }
+}
+
+/// The Grain LFSR, seeded with a parameter set. It yields the bits the reference implementation samples field
+/// elements from.
+#[derive(Debug, Clone)]
+pub struct Grain<F> {
+    state: [bool; STATE],
+    next_bit: usize,
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField> Grain<F> {
+    /// Seed the LFSR for a prime field, with `t` the width of the permutation, and `r_f` and `r_p` its number of full
+    /// and partial rounds
+    pub fn new(sbox: SboxType, t: u16, r_f: u16, r_p: u16) -> Self {
+        // The state starts with the parameters, most significant bit first, and is padded with ones
+        let mut state = [true; STATE];
+        let mut set_bits = |offset: usize, len: usize, value: u16| {
+            for i in 0..len {
+                state[offset + len - 1 - i] = (value >> i) & 1 == 1;
+            }
+        };
+        // Prime fields are field type 1
+        set_bits(0, 2, 1);
+        set_bits(2, 4, sbox.tag());
+        set_bits(6, 12, F::NUM_BITS as u16);
+        set_bits(18, 12, t);
+        set_bits(30, 10, r_f);
+        set_bits(40, 10, r_p);
+
+        let mut grain = Grain {
+            state,
+            next_bit: STATE,
+            _field: PhantomData,
+        };
+        // The first 160 bits are discarded
+        for _ in 0..20 {
+            grain.load_next_8_bits();
+            grain.next_bit = STATE;
+        }
+        grain
+    }
+
+    // Clock the LFSR 8 times: b_{i+80} = b_{i+62} + b_{i+51} + b_{i+38} + b_{i+23} + b_{i+13} + b_i
+    fn load_next_8_bits(&mut self) {
+        let mut new_bits = [false; 8];
+        for (i, bit) in new_bits.iter_mut().enumerate() {
+            *bit = self.state[i + 62]
+                ^ self.state[i + 51]
+                ^ self.state[i + 38]
+                ^ self.state[i + 23]
+                ^ self.state[i + 13]
+                ^ self.state[i];
+        }
+        self.state.rotate_left(8);
+        self.next_bit -= 8;
+        self.state[self.next_bit..self.next_bit + 8].copy_from_slice(&new_bits);
+    }
+
+    fn get_next_bit(&mut self) -> bool {
+        if self.next_bit == STATE {
+            self.load_next_8_bits();
+        }
+        let bit = self.state[self.next_bit];
+        self.next_bit += 1;
+        bit
+    }
+
+    // The reference implementation reads the bits as an integer most significant bit first, while our reprs are
+    // little-endian
+    fn next_bytes(&mut self, bytes: &mut [u8]) {
+        let num_bits = F::NUM_BITS as usize;
+        for (i, bit) in self.take(num_bits).enumerate() {
+            let i = num_bits - 1 - i;
+            if bit {
+                bytes[i / 8] |= 1 << (i % 8);
+            }
+        }
+    }
+
+    /// The next field element, sampled with rejection: integers that are not below the modulus are skipped
+    pub fn next_field_element(&mut self) -> F {
+        loop {
+            let mut repr = F::Repr::default();
+            self.next_bytes(repr.as_mut());
+            if let Some(element) = Option::from(F::from_repr(repr)) {
+                break element;
+            }
+        }
+    }
+}
+
+impl<F: FromUniformBytes<64>> Grain<F> {
+    /// The next field element, reducing the sampled integer modulo the field instead of rejecting it
+    pub fn next_field_element_without_rejection(&mut self) -> F {
+        let mut bytes = [0u8; 64];
+        self.next_bytes(&mut bytes);
+        F::from_uniform_bytes(&bytes)
+    }
+}
+
+impl<F: PrimeField> Iterator for Grain<F> {
+    type Item = bool;
+
+    // The output bits are filtered in pairs: if the first bit is a 1 the second bit is output, otherwise it is
+    // discarded
+    fn next(&mut self) -> Option<bool> {
+        while !self.get_next_bit() {
+            self.get_next_bit();
+        }
+        Some(self.get_next_bit())
+    }
+}
+
+// The product of two matrices
+#[allow(clippy::needless_range_loop)]
+fn mat_mul<F: PrimeField, const T: usize>(a: &Mds<F, T>, b: &Mds<F, T>) -> Mds<F, T> {
+    let mut product = [[F::ZERO; T]; T];
+    for i in 0..T {
+        for j in 0..T {
+            product[i][j] = (0..T).fold(F::ZERO, |acc, k| acc + a[i][k] * b[k][j]);
+        }
+    }
+    product
+}
+
+// The dimension of the span of e_0, M e_0, M^2 e_0, ..., the smallest subspace invariant under `m` that contains e_0
+fn krylov_dimension<F: PrimeField, const T: usize>(m: &Mds<F, T>) -> usize {
+    // Rows in echelon form, with the index of their first non-zero element
+    let mut basis: Vec<(usize, [F; T])> = vec![];
+    let mut v = [F::ZERO; T];
+    v[0] = F::ONE;
+    loop {
+        let mut w = v;
+        for (pivot, row) in basis.iter() {
+            let factor = w[*pivot] * row[*pivot].invert().unwrap();
+            for (w, row) in w.iter_mut().zip(row) {
+                *w -= factor * row;
+            }
+        }
+        match w.iter().position(|x| !bool::from(x.is_zero())) {
+            Some(pivot) => basis.push((pivot, w)),
+            None => return basis.len(),
+        }
+        if basis.len() == T {
+            return T;
+        }
+        v = m.map(|row| {
+            row.iter()
+                .zip(v.iter())
+                .fold(F::ZERO, |acc, (m, v)| acc + *m * v)
+        });
+    }
+}
+
+// A basis of the vectors v with e_0 M^k v = 0 for every k, the largest subspace invariant under `m` that never
+// activates the S-box. Each basis vector is paired with the coordinate it is 1 at, where the other basis vectors are 0.
+fn inactive_subspace<F: PrimeField, const T: usize>(m: &Mds<F, T>) -> Vec<(usize, [F; T])> {
+    // By Cayley-Hamilton, e_0 M^k for k < T span all of them
+    let mut rows = vec![];
+    let mut row = [F::ZERO; T];
+    row[0] = F::ONE;
+    for _ in 0..T {
+        rows.push(row);
+        row = std::array::from_fn(|j| (0..T).fold(F::ZERO, |acc, k| acc + row[k] * m[k][j]));
+    }
+
+    // Reduced row echelon form
+    let mut pivots = vec![];
+    for column in 0..T {
+        let r = pivots.len();
+        let pivot = match (r..T).find(|i| !bool::from(rows[*i][column].is_zero())) {
+            Some(pivot) => pivot,
+            None => continue,
+        };
+        rows.swap(r, pivot);
+        let inverse = rows[r][column].invert().unwrap();
+        rows[r] = rows[r].map(|x| x * inverse);
+        let pivot_row = rows[r];
+        for (i, row) in rows.iter_mut().enumerate() {
+            let factor = row[column];
+            if i != r && !bool::from(factor.is_zero()) {
+                for (x, y) in row.iter_mut().zip(pivot_row.iter()) {
+                    *x -= factor * y;
+                }
+            }
+        }
+        pivots.push(column);
+    }
+
+    (0..T)
+        .filter(|column| !pivots.contains(column))
+        .map(|free| {
+            let mut v = [F::ZERO; T];
+            v[free] = F::ONE;
+            for (row, pivot) in rows.iter().zip(pivots.iter()) {
+                v[*pivot] = -row[free];
+            }
+            (free, v)
+        })
+        .collect()
+}
+
+// The characteristic polynomial of a square matrix, lowest degree first, with the Faddeev-LeVerrier algorithm
+fn characteristic_polynomial<F: PrimeField>(m: &[Vec<F>]) -> Vec<F> {
+    let d = m.len();
+    let mut coefficients = vec![F::ZERO; d + 1];
+    coefficients[d] = F::ONE;
+    // M_k = m M_{k-1} + c_{d-k+1} I, starting from M_0 = 0, and c_{d-k} = -tr(m M_k) / k
+    let mut m_k = vec![vec![F::ZERO; d]; d];
+    for k in 1..d + 1 {
+        m_k = (0..d)
+            .map(|i| {
+                (0..d)
+                    .map(|j| {
+                        let product = (0..d).fold(F::ZERO, |acc, l| acc + m[i][l] * m_k[l][j]);
+                        if i == j {
+                            product + coefficients[d - k + 1]
+                        } else {
+                            product
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let trace = (0..d).fold(F::ZERO, |acc, i| {
+            (0..d).fold(acc, |acc, l| acc + m[i][l] * m_k[l][i])
+        });
+        coefficients[d - k] = -trace * F::from(k as u64).invert().unwrap();
+    }
+    coefficients
+}
+
+// The remainder of the division of `a` by a non-zero polynomial `f`, both lowest degree first
+fn poly_rem<F: PrimeField>(mut a: Vec<F>, f: &[F]) -> Vec<F> {
+    let leading_inverse = f.last().unwrap().invert().unwrap();
+    while a.len() >= f.len() {
+        let factor = *a.last().unwrap() * leading_inverse;
+        let shift = a.len() - f.len();
+        for (a, f) in a[shift..].iter_mut().zip(f) {
+            *a -= factor * f;
+        }
+        a.pop();
+    }
+    while matches!(a.last(), Some(x) if bool::from(x.is_zero())) {
+        a.pop();
+    }
+    a
+}
+
+fn poly_mul_rem<F: PrimeField>(a: &[F], b: &[F], f: &[F]) -> Vec<F> {
+    let mut product = vec![F::ZERO; (a.len() + b.len()).saturating_sub(1)];
+    for (i, a) in a.iter().enumerate() {
+        for (j, b) in b.iter().enumerate() {
+            product[i + j] += *a * b;
+        }
+    }
+    poly_rem(product, f)
+}
+
+// Whether a polynomial of positive degree has a root in the field, i.e. shares a factor with x^p - x
+fn has_root<F: PrimeField>(f: &[F]) -> bool {
+    let x = [F::ZERO, F::ONE];
+    // x^p mod f, from the bits of p - 1, whose representation is little-endian for the fields used here
+    let mut power = vec![F::ONE];
+    for byte in (-F::ONE).to_repr().as_ref().iter().rev() {
+        for i in (0..8).rev() {
+            power = poly_mul_rem(&power, &power, f);
+            if (byte >> i) & 1 == 1 {
+                power = poly_mul_rem(&power, &x, f);
+            }
+        }
+    }
+    power = poly_mul_rem(&power, &x, f);
+
+    // gcd(f, x^p - x)
+    power.resize(power.len().max(2), F::ZERO);
+    power[1] -= F::ONE;
+    let mut a = f.to_vec();
+    let mut b = poly_rem(power, f);
+    while !b.is_empty() {
+        let r = poly_rem(a, &b);
+        a = b;
+        b = r;
+    }
+    a.len() > 1
+}
+
+// Whether `m` has an eigenvector with an eigenvalue in the field and a zero first element. `m` leaves the subspace of
+// such an eigenvector invariant, so its trail never activates the S-box.
+fn has_inactive_eigenvector<F: PrimeField, const T: usize>(m: &Mds<F, T>) -> bool {
+    let basis = inactive_subspace(m);
+    if basis.is_empty() {
+        return false;
+    }
+    // The restriction of `m` to the subspace, in the coordinates of `basis`
+    let restriction = basis
+        .iter()
+        .map(|(free, _)| {
+            basis
+                .iter()
+                .map(|(_, v)| (0..T).fold(F::ZERO, |acc, k| acc + m[*free][k] * v[k]))
+                .collect()
+        })
+        .collect::<Vec<Vec<F>>>();
+    has_root(&characteristic_polynomial(&restriction))
+}
+
+/// Whether `mds` passes the checks the reference implementation runs on the MDS matrices it samples, for partial
+/// rounds with a single S-box on the first element of the state. These are algorithms 1 to 3 of Grassi, Rechberger
+/// and Schofnegger, "Proving Resistance Against Infinitely Long Subspace Trails: How to Choose the Linear Layer", with
+/// matrices acting on column vectors:
+///
+/// - for $i < T$, $M^i$ is not a multiple of the identity, and has no eigenvector with an eigenvalue in the field and a
+///   zero first element (algorithm 1);
+/// - for every power $M^r$ up to $r = 4T$, e_0, M^r e_0, M^{2r} e_0, ... span the whole state (algorithms 2 and 3).
+pub fn is_secure_mds<F: PrimeField, const T: usize>(mds: &Mds<F, T>) -> bool {
+    let is_scalar = |m: &Mds<F, T>| {
+        m.iter().enumerate().all(|(i, row)| {
+            row.iter()
+                .enumerate()
+                .all(|(j, entry)| *entry == if i == j { m[0][0] } else { F::ZERO })
+        })
+    };
+
+    let mut power = *mds;
+    for i in 1..4 * T + 1 {
+        if i > 1 {
+            power = mat_mul(&power, mds);
+        }
+        if i < T && (is_scalar(&power) || has_inactive_eigenvector(&power)) {
+            return false;
+        }
+        if krylov_dimension(&power) < T {
+            return false;
+        }
+    }
+    true
+}
+
+/// Sample Cauchy MDS matrices from `grain` until one passes `is_secure_mds`. Returns that matrix, its inverse, and the
+/// number of matrices that were rejected before it, which is what `Spec::secure_mds` reports.
+#[allow(clippy::needless_range_loop)]
+pub fn generate_mds<F: FromUniformBytes<64>, const T: usize>(
+    grain: &mut Grain<F>,
+) -> (Mds<F, T>, Mds<F, T>, usize) {
+    let mut rejected = 0;
+    let (xs, ys, mds) = loop {
+        // Two sets of T elements that are all distinct
+        let (xs, ys) = loop {
+            let mut values = (0..2 * T)
+                .map(|_| grain.next_field_element_without_rejection())
+                .collect::<Vec<F>>();
+            let unique = (0..values.len()).all(|i| (0..i).all(|j| values[i] != values[j]));
+            if unique {
+                let ys = values.split_off(T);
+                break (values, ys);
+            }
+        };
+
+        // a_ij = 1 / (x_i + y_j), the formulation of the reference implementation
+        let mut mds = [[F::ZERO; T]; T];
+        for i in 0..T {
+            for j in 0..T {
+                let sum = xs[i] + ys[j];
+                assert!(
+                    !bool::from(sum.is_zero()),
+                    "cannot invert the Cauchy matrix"
+                );
+                mds[i][j] = sum.invert().unwrap();
+            }
+        }
+        if is_secure_mds(&mds) {
+            break (xs, ys, mds);
+        }
+        rejected += 1;
+    };
+
+    // The inverse of the Cauchy matrix a_ij = 1 / (x_i - y_j) is b_ij = (x_j - y_i) A_j(y_i) B_i(x_j), with A_j and
+    // B_i the Lagrange polynomials of xs and ys (Schechter 1959). Negating ys gives the inverse of our matrix.
+    let lagrange = |points: &[F], j: usize, x: F| {
+        points
+            .iter()
+            .enumerate()
+            .filter(|(m, _)| *m != j)
+            .fold(F::ONE, |acc, (_, point)| {
+                // The points are distinct, so the denominator is never zero
+                let denominator: F = points[j] - point;
+                acc * (x - point) * denominator.invert().unwrap()
+            })
+    };
+    let neg_ys = ys.iter().map(|y| -*y).collect::<Vec<_>>();
+    let mut mds_inv = [[F::ZERO; T]; T];
+    for i in 0..T {
+        for j in 0..T {
+            mds_inv[i][j] =
+                (xs[j] - neg_ys[i]) * lagrange(&xs, j, neg_ys[i]) * lagrange(&neg_ys, i, xs[j]);
+        }
+    }
+
+    (mds, mds_inv, rejected)
+}
+
+/// The round constants, secure MDS matrix and its inverse of the $x^5$ Poseidon permutation of width `T` with
+/// `full_rounds` and `partial_rounds`, along with the number of MDS matrices rejected by `is_secure_mds`.
+pub fn generate_constants<F: FromUniformBytes<64>, const T: usize>(
+    full_rounds: usize,
+    partial_rounds: usize,
+) -> (Vec<[F; T]>, Mds<F, T>, Mds<F, T>, usize) {
+    let mut grain = Grain::new(
+        SboxType::Pow,
+        T as u16,
+        full_rounds as u16,
+        partial_rounds as u16,
+    );
+
+    let round_constants = (0..full_rounds + partial_rounds)
+        .map(|_| {
+            let mut constants = [F::ZERO; T];
+            for constant in constants.iter_mut() {
+                *constant = grain.next_field_element();
+            }
+            constants
+        })
+        .collect();
+    let (mds, mds_inv, rejected) = generate_mds(&mut grain);
+
+    (round_constants, mds, mds_inv, rejected)
+}
+
+/// Same as `generate_constants`, with the rounds of the spec `S`. Panics if `S::secure_mds` does not match the number
+/// of MDS matrices that were rejected, so that the constants of a spec are the ones `halo2_gadgets` derives for it.
+pub fn generate_spec_constants<
+    F: FromUniformBytes<64>,
+    S: Spec<F, T, RATE>,
+    const T: usize,
+    const RATE: usize,
+>() -> (Vec<[F; T]>, Mds<F, T>, Mds<F, T>) {
+    let (round_constants, mds, mds_inv, rejected) =
+        generate_constants(S::full_rounds(), S::partial_rounds());
+    assert_eq!(
+        S::secure_mds(),
+        rejected,
+        "the spec does not select the secure MDS matrix"
+    );
+    (round_constants, mds, mds_inv)
+}
+
+/// Declares a Poseidon-128 spec using the $x^5$ S-box, with a width of `$width` field elements, a rate of `$rate`,
+/// $R_F = 8$ and `$partial_rounds` partial rounds, for both Pasta fields.
+///
+/// The constants are generated with `generate_spec_constants` the first time they are needed, and tested against the
+/// ones `halo2_gadgets` derives for the same rounds. On both Pasta fields the first MDS matrix the LFSR samples passes
+/// `is_secure_mds`, which is why `secure_mds` is 0; `generate_spec_constants` panics if it does not.
+macro_rules! p128_pow5_spec {
+    ($(#[$attr:meta])* $name:ident, $width:literal, $rate:literal, $partial_rounds:literal) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy)]
+        pub struct $name;
+
+        $crate::utils::grain::p128_pow5_spec!(
+            @impl $name, halo2curves::pasta::Fp, $width, $rate, $partial_rounds
+        );
+        $crate::utils::grain::p128_pow5_spec!(
+            @impl $name, halo2curves::pasta::Fq, $width, $rate, $partial_rounds
+        );
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+            use ff::FromUniformBytes;
+            use halo2_gadgets::poseidon::primitives::{generate_constants, Spec};
+            use halo2curves::pasta::{Fp, Fq};
+
+            // The constants are the ones the Grain implementation of `halo2_gadgets` derives for the same rounds
+            fn assert_matches_upstream<F: FromUniformBytes<64> + Ord>()
+            where
+                $name: Spec<F, $width, $rate>,
+            {
+                assert_eq!(
+                    <$name as Spec<F, $width, $rate>>::constants(),
+                    generate_constants::<F, $name, $width, $rate>()
+                );
+            }
+
+            #[test]
+            fn test_fp_matches_upstream() {
+                assert_matches_upstream::<Fp>();
+            }
+
+            #[test]
+            fn test_fq_matches_upstream() {
+                assert_matches_upstream::<Fq>();
+            }
+        }
+    };
+    (@impl $name:ident, $field:ty, $width:literal, $rate:literal, $partial_rounds:literal) => {
+        impl halo2_gadgets::poseidon::primitives::Spec<$field, $width, $rate> for $name {
+            fn full_rounds() -> usize {
+                8
+            }
+
+            fn partial_rounds() -> usize {
+                $partial_rounds
+            }
+
+            fn sbox(val: $field) -> $field {
+                halo2_proofs::arithmetic::Field::pow_vartime(&val, &[5])
+            }
+
+            fn secure_mds() -> usize {
+                0
+            }
+
+            fn constants() -> (
+                Vec<[$field; $width]>,
+                halo2_gadgets::poseidon::primitives::Mds<$field, $width>,
+                halo2_gadgets::poseidon::primitives::Mds<$field, $width>,
+            ) {
+                static CONSTANTS: std::sync::OnceLock<(
+                    Vec<[$field; $width]>,
+                    halo2_gadgets::poseidon::primitives::Mds<$field, $width>,
+                    halo2_gadgets::poseidon::primitives::Mds<$field, $width>,
+                )> = std::sync::OnceLock::new();
+                CONSTANTS
+                    .get_or_init(
+                        $crate::utils::grain::generate_spec_constants::<_, Self, $width, $rate>,
+                    )
+                    .clone()
+            }
+        }
+    };
+}
+pub(crate) use p128_pow5_spec;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{fp, fq};
+    use ff::Field;
+    use halo2_proofs::halo2curves::bn256::Fr;
+    use halo2curves::pasta::{Fp, Fq};
+
+    fn assert_same<F: PrimeField, const T: usize>(a: &[[F; T]], b: &[[F; T]]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            for (a, b) in a.iter().zip(b) {
+                assert_eq!(a.to_repr().as_ref(), b.to_repr().as_ref());
+            }
+        }
+    }
+
+    // The tables were generated with "Secure MDS: 0", the first matrix the script sampled
+    #[test]
+    fn test_fp_constants() {
+        let (round_constants, mds, mds_inv, rejected) = generate_constants::<Fp, 3>(8, 56);
+        assert_same(&round_constants, &fp::ROUND_CONSTANTS);
+        assert_same(&mds, &fp::MDS);
+        assert_same(&mds_inv, &fp::MDS_INV);
+        assert_eq!(rejected, 0);
+    }
+
+    #[test]
+    fn test_fq_constants() {
+        let (round_constants, mds, mds_inv, rejected) = generate_constants::<Fq, 3>(8, 56);
+        assert_same(&round_constants, &fq::ROUND_CONSTANTS);
+        assert_same(&mds, &fq::MDS);
+        assert_same(&mds_inv, &fq::MDS_INV);
+        assert_eq!(rejected, 0);
+    }
+
+    // The first round constant and MDS entry `generate_parameters_grain.sage` derives for the BN254 scalar field with
+    // the standard rounds of the width, the parameters circomlib uses
+    fn assert_known_answer<const T: usize>(
+        partial_rounds: usize,
+        round_constant: [u64; 4],
+        mds_entry: [u64; 4],
+    ) {
+        let (round_constants, mds, _, rejected) = generate_constants::<Fr, T>(8, partial_rounds);
+        assert_eq!(round_constants[0][0], Fr::from_raw(round_constant));
+        assert_eq!(mds[0][0], Fr::from_raw(mds_entry));
+        assert_eq!(rejected, 0);
+    }
+
+    #[test]
+    fn test_known_answers() {
+        assert_known_answer::<5>(
+            60,
+            [
+                0x4f3c2bd81a6da891,
+                0xd889bb4ebd47c386,
+                0x7f53e29ccac98ed7,
+                0x0eb544fee2815dda,
+            ],
+            [
+                0x4fc6fdbcf38d7da1,
+                0x9f22e57ace3cd7f6,
+                0x080b0af133b9e436,
+                0x251e7fdf99591080,
+            ],
+        );
+        assert_known_answer::<9>(
+            63,
+            [
+                0x1a580bd42cda0e77,
+                0x58d66e0c07b9cc00,
+                0x8be7bc457f2756d5,
+                0x2088ce9534577bf3,
+            ],
+            [
+                0xaa24925d158cac56,
+                0xd1c64bc22f1c556a,
+                0xcf0a142a3be27749,
+                0x0190f922d97c8a7d,
+            ],
+        );
+        assert_known_answer::<12>(
+            60,
+            [
+                0xb4ad5c6e23ad4e5f,
+                0xf775c642b300da2f,
+                0x89f071140a60749c,
+                0x1512df0135b66925,
+            ],
+            [
+                0x5fca86539b272dc6,
+                0x64386ad905e24057,
+                0x1b9fec37376253cf,
+                0x2cb13179d6e8a4ad,
+            ],
+        );
+    }
+
+    #[test]
+    #[allow(clippy::needless_range_loop)]
+    fn test_mds_inverse() {
+        let (_, mds, mds_inv, _) = generate_constants::<Fp, 5>(8, 60);
+        for i in 0..5 {
+            for j in 0..5 {
+                let product = (0..5).fold(Fp::ZERO, |acc, k| acc + mds[i][k] * mds_inv[k][j]);
+                assert_eq!(product, if i == j { Fp::ONE } else { Fp::ZERO });
+            }
+        }
+    }
+
+    #[test]
+    fn test_insecure_mds() {
+        let from = |m: [[u64; 3]; 3]| m.map(|row| row.map(Fp::from));
+        // Every subspace is invariant under the identity
+        assert!(!is_secure_mds(&from([[1, 0, 0], [0, 1, 0], [0, 0, 1]])));
+        // e_0 and e_1 span an invariant subspace
+        assert!(!is_secure_mds(&from([[2, 3, 0], [5, 7, 0], [0, 0, 11]])));
+        // e_1 and e_2 never reach the S-box, and span eigenvectors with eigenvalues in the field
+        assert!(!is_secure_mds(&from([[2, 0, 0], [3, 5, 7], [11, 13, 17]])));
+        // e_2 is an eigenvector that never reaches the S-box
+        assert!(!is_secure_mds(&from([[2, 0, 0], [3, 7, 0], [5, 0, 11]])));
+        // e_1 and e_2 span a subspace that never reaches the S-box, but it has no eigenvector: the eigenvalues of its
+        // restriction are the roots of x^2 - x - 3, and 13 is not a square
+        assert!(is_secure_mds(&from([[2, 0, 0], [3, 0, 1], [5, 3, 1]])));
+        // The cube of a permutation matrix is the identity
+        assert!(!is_secure_mds(&from([[0, 1, 0], [0, 0, 1], [1, 0, 0]])));
+        assert!(is_secure_mds(&fp::MDS));
+        assert!(is_secure_mds(&fq::MDS));
+    }
+}
@@ -0,0 +1,12 @@
+use super::grain::p128_pow5_spec;
+
+p128_pow5_spec!(
+    /// Poseidon-128 using the $x^5$ S-box, with a width of 12 field elements, so that a single permutation absorbs up to
+    /// eleven elements.
+    ///
+    /// The round numbers for this width are $R_F = 8, R_P = 60$, as in the table of the reference implementation.
+    P128Pow5T12,
+    12,
+    11,
+    60
+);
@@ -1,11 +1,14 @@
 // Had to move this here to make it cloneable...
+use std::sync::OnceLock;
+
 use halo2_proofs::arithmetic::Field;
+// The BN254 types have to be the ones of the halo2curves that halo2_proofs is built against
+use halo2_proofs::halo2curves::bn256::Fr;
 use halo2curves::pasta::{pallas::Base as Fp, vesta::Base as Fq};
 
 use halo2_gadgets::poseidon::primitives::{Mds, Spec};
 
-use super::fp as SuperFp;
-use super::fq as SuperFq;
+use super::grain::generate_spec_constants;
 
 /// Poseidon-128 using the $x^5$ S-box, with a width of 3 field elements, and the
 /// standard number of rounds for 128-bit security "with margin".
@@ -13,6 +16,13 @@ use super::fq as SuperFq;
 /// The standard specification for this set of parameters (on either of the Pasta
 /// fields) uses $R_F = 8, R_P = 56$. This is conveniently an even number of
 /// partial rounds, making it easier to construct a Halo 2 circuit.
+///
+/// Over the BN254 scalar field the standard is $R_P = 57$ instead, which we round up
+/// to $R_P = 58$ for the circuit.
+///
+/// The constants are generated with `utils::grain` the first time they are needed. On
+/// all three fields the first MDS matrix the LFSR samples passes the checks of the
+/// reference script, so `secure_mds` is 0: `generate_spec_constants` panics otherwise.
 #[derive(Debug, Clone, Copy)]
 pub struct P128Pow5T3;
 
@@ -30,15 +40,14 @@ impl Spec<Fp, 3, 2> for P128Pow5T3 {
     }
 
     fn secure_mds() -> usize {
-        unimplemented!()
+        0
     }
 
     fn constants() -> (Vec<[Fp; 3]>, Mds<Fp, 3>, Mds<Fp, 3>) {
-        (
-            SuperFp::ROUND_CONSTANTS[..].to_vec(),
-            SuperFp::MDS,
-            SuperFp::MDS_INV,
-        )
+        static CONSTANTS: OnceLock<(Vec<[Fp; 3]>, Mds<Fp, 3>, Mds<Fp, 3>)> = OnceLock::new();
+        CONSTANTS
+            .get_or_init(generate_spec_constants::<_, Self, 3, 2>)
+            .clone()
     }
 }
 
@@ -56,14 +65,126 @@ impl Spec<Fq, 3, 2> for P128Pow5T3 {
     }
 
     fn secure_mds() -> usize {
-        unimplemented!()
+        0
     }
 
     fn constants() -> (Vec<[Fq; 3]>, Mds<Fq, 3>, Mds<Fq, 3>) {
-        (
-            SuperFq::ROUND_CONSTANTS[..].to_vec(),
-            SuperFq::MDS,
-            SuperFq::MDS_INV,
-        )
+        static CONSTANTS: OnceLock<(Vec<[Fq; 3]>, Mds<Fq, 3>, Mds<Fq, 3>)> = OnceLock::new();
+        CONSTANTS
+            .get_or_init(generate_spec_constants::<_, Self, 3, 2>)
+            .clone()
+    }
+}
+
+impl Spec<Fr, 3, 2> for P128Pow5T3 {
+    fn full_rounds() -> usize {
+        8
+    }
+
+    fn partial_rounds() -> usize {
+        58
+    }
+
+    fn sbox(val: Fr) -> Fr {
+        val.pow_vartime(&[5])
+    }
+
+    fn secure_mds() -> usize {
+        0
+    }
+
+    fn constants() -> (Vec<[Fr; 3]>, Mds<Fr, 3>, Mds<Fr, 3>) {
+        static CONSTANTS: OnceLock<(Vec<[Fr; 3]>, Mds<Fr, 3>, Mds<Fr, 3>)> = OnceLock::new();
+        CONSTANTS
+            .get_or_init(generate_spec_constants::<_, Self, 3, 2>)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff::FromUniformBytes;
+    use halo2_gadgets::poseidon::primitives::{
+        self as poseidon, generate_constants, ConstantLength, P128Pow5T3 as Upstream,
+    };
+    use rand_core::OsRng;
+
+    // The parameters of the spec are the ones upstream, and the constants are the ones the Grain LFSR derives from them
+    fn assert_matches_upstream<F: FromUniformBytes<64> + Ord>()
+    where
+        P128Pow5T3: Spec<F, 3, 2>,
+        Upstream: Spec<F, 3, 2>,
+    {
+        assert_eq!(
+            <P128Pow5T3 as Spec<F, 3, 2>>::full_rounds(),
+            <Upstream as Spec<F, 3, 2>>::full_rounds()
+        );
+        assert_eq!(
+            <P128Pow5T3 as Spec<F, 3, 2>>::partial_rounds(),
+            <Upstream as Spec<F, 3, 2>>::partial_rounds()
+        );
+        assert_eq!(
+            <P128Pow5T3 as Spec<F, 3, 2>>::secure_mds(),
+            <Upstream as Spec<F, 3, 2>>::secure_mds()
+        );
+
+        let constants = <P128Pow5T3 as Spec<F, 3, 2>>::constants();
+        assert_eq!(constants, <Upstream as Spec<F, 3, 2>>::constants());
+        assert_eq!(constants, generate_constants::<F, P128Pow5T3, 3, 2>());
+        assert_eq!(constants, generate_spec_constants::<F, P128Pow5T3, 3, 2>());
+
+        for _ in 0..10 {
+            let message = [F::random(OsRng), F::random(OsRng)];
+            assert_eq!(
+                poseidon::Hash::<_, P128Pow5T3, ConstantLength<2>, 3, 2>::init().hash(message),
+                poseidon::Hash::<_, Upstream, ConstantLength<2>, 3, 2>::init().hash(message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fp_matches_upstream() {
+        assert_matches_upstream::<Fp>();
+    }
+
+    #[test]
+    fn test_fq_matches_upstream() {
+        assert_matches_upstream::<Fq>();
+    }
+
+    // With the standard R_P = 57, the generator gives the BN254 constants of circomlib and the reference script
+    #[test]
+    fn test_fr_known_answer() {
+        let (round_constants, mds, _, rejected) =
+            crate::utils::grain::generate_constants::<Fr, 3>(8, 57);
+        assert_eq!(round_constants.len(), 65);
+        assert_eq!(
+            round_constants[0][0],
+            Fr::from_raw([
+                0x8d21d47304cd8e6e,
+                0x14c4993c11bb2993,
+                0xd05986d656f40c21,
+                0x0ee9a592ba9a9518,
+            ])
+        );
+        assert_eq!(
+            mds[0][0],
+            Fr::from_raw([
+                0xfedb68592ba8118b,
+                0x94be7c11ad24378b,
+                0xb2b70caf5c36a7b1,
+                0x109b7f411ba0e4c9,
+            ])
+        );
+        assert_eq!(rejected, 0);
+
+        // The spec rounds R_P up, which changes the constants but still selects the first MDS matrix
+        let (_, _, _, rejected) = crate::utils::grain::generate_constants::<Fr, 3>(8, 58);
+        assert_eq!(rejected, <P128Pow5T3 as Spec<Fr, 3, 2>>::secure_mds());
+        assert_eq!(
+            <P128Pow5T3 as Spec<Fr, 3, 2>>::constants(),
+            generate_spec_constants::<Fr, P128Pow5T3, 3, 2>()
+        );
     }
 }
@@ -0,0 +1,12 @@
+use super::grain::p128_pow5_spec;
+
+p128_pow5_spec!(
+    /// Poseidon-128 using the $x^5$ S-box, with a width of 5 field elements, which lets a single permutation absorb the
+    /// four children of a node in an arity-4 merkle tree.
+    ///
+    /// The round numbers for this width are $R_F = 8, R_P = 60$, as in the table of the reference implementation.
+    P128Pow5T5,
+    5,
+    4,
+    60
+);
@@ -0,0 +1,13 @@
+use super::grain::p128_pow5_spec;
+
+p128_pow5_spec!(
+    /// Poseidon-128 using the $x^5$ S-box, with a width of 9 field elements, so that a single permutation absorbs up to
+    /// eight elements, e.g. the attributes of a credential.
+    ///
+    /// The round numbers for this width are $R_F = 8, R_P = 63$, rounded up to $R_P = 64$ because `Pow5Chip` needs an
+    /// even number of partial rounds.
+    P128Pow5T9,
+    9,
+    8,
+    64
+);
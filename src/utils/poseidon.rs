@@ -0,0 +1,198 @@
+//! Native Poseidon hashing for messages whose length is only known at runtime, and a duplex sponge.
+//!
+//! `halo2_gadgets` only hashes messages of a `ConstantLength<L>`, and keeps the sponge it builds the hash on private.
+//! `hash_variable_length` runs the same sponge for a slice of any length, so its digest of a message of length `n` is
+//! the one `ConstantLength<n>` gives, and the one `PoseidonChip::hash_variable_length` computes in a circuit.
+//! `Sponge` exposes that sponge, and is the native counterpart of `PoseidonSponge`.
+
+use std::marker::PhantomData;
+
+use ff::PrimeField;
+use halo2_gadgets::poseidon::primitives::Spec;
+
+/// The Poseidon permutation of the spec `S`
+pub fn permute<F: PrimeField, S: Spec<F, T, RATE>, const T: usize, const RATE: usize>(
+    state: &mut [F; T],
+) {
+    let (round_constants, mds, _) = S::constants();
+    let half_full_rounds = S::full_rounds() / 2;
+    let partial_rounds = S::partial_rounds();
+
+    let apply_mds = |state: &mut [F; T]| {
+        let mut new_state = [F::ZERO; T];
+        for (new_word, row) in new_state.iter_mut().zip(mds.iter()) {
+            *new_word = row
+                .iter()
+                .zip(state.iter())
+                .fold(F::ZERO, |acc, (m, word)| acc + *m * word);
+        }
+        *state = new_state;
+    };
+
+    for (round, constants) in round_constants.iter().enumerate() {
+        for (word, constant) in state.iter_mut().zip(constants) {
+            *word += constant;
+        }
+        let full = round < half_full_rounds || round >= half_full_rounds + partial_rounds;
+        if full {
+            for word in state.iter_mut() {
+                *word = S::sbox(*word);
+            }
+        } else {
+            state[0] = S::sbox(state[0]);
+        }
+        apply_mds(state);
+    }
+}
+
+/// A duplex sponge with the absorbing and squeezing of the `halo2_gadgets` one, which can also absorb again after
+/// squeezing. The inputs absorbed since the last permutation are added to the rate before the next one, so the
+/// permutations of a sponge that only absorbs and then squeezes are the ones of a `ConstantLength` hash.
+#[derive(Debug, Clone)]
+pub struct Sponge<F: PrimeField, S: Spec<F, T, RATE>, const T: usize, const RATE: usize> {
+    state: [F; T],
+    absorbing: Vec<F>,
+    output: Vec<F>,
+    squeezing: bool,
+    _spec: PhantomData<S>,
+}
+
+impl<F: PrimeField, S: Spec<F, T, RATE>, const T: usize, const RATE: usize> Sponge<F, S, T, RATE> {
+    /// A sponge with `domain` as its initial capacity element
+    pub fn new(domain: F) -> Self {
+        let mut state = [F::ZERO; T];
+        state[RATE] = domain;
+        Self {
+            state,
+            absorbing: vec![],
+            output: vec![],
+            squeezing: false,
+            _spec: PhantomData,
+        }
+    }
+
+    pub fn absorb(&mut self, value: F) {
+        if self.squeezing {
+            self.squeezing = false;
+            self.output.clear();
+        }
+        if self.absorbing.len() == RATE {
+            self.permute();
+        }
+        self.absorbing.push(value);
+    }
+
+    pub fn squeeze(&mut self) -> F {
+        if !self.squeezing || self.output.is_empty() {
+            self.permute();
+            self.output = self.state[..RATE].to_vec();
+            self.squeezing = true;
+        }
+        self.output.remove(0)
+    }
+
+    fn permute(&mut self) {
+        for (word, value) in self.state.iter_mut().zip(self.absorbing.drain(..)) {
+            *word += value;
+        }
+        permute::<F, S, T, RATE>(&mut self.state);
+    }
+}
+
+/// Hash `message` with the sponge and domain of `ConstantLength<message.len()>`: the capacity starts at
+/// $2^{64} \cdot n$, the message is absorbed `RATE` elements at a time and padded with zeros, and there is always at
+/// least one permutation.
+pub fn hash_variable_length<
+    F: PrimeField,
+    S: Spec<F, T, RATE>,
+    const T: usize,
+    const RATE: usize,
+>(
+    message: &[F],
+) -> F {
+    let mut sponge = Sponge::<F, S, T, RATE>::new(F::from_u128((message.len() as u128) << 64));
+    for value in message {
+        sponge.absorb(*value);
+    }
+    sponge.squeeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::p128pow5t3::P128Pow5T3;
+    use crate::utils::p128pow5t5::P128Pow5T5;
+    use ff::Field;
+    use halo2_gadgets::poseidon::primitives::{self as poseidon, ConstantLength};
+    use halo2curves::pasta::{Fp, Fq};
+    use rand_core::OsRng;
+
+    fn assert_constant_length<F, S, const T: usize, const RATE: usize, const L: usize>()
+    where
+        F: PrimeField,
+        S: Spec<F, T, RATE>,
+    {
+        let message: [F; L] = [(); L].map(|_| F::random(OsRng));
+        assert_eq!(
+            hash_variable_length::<F, S, T, RATE>(&message),
+            poseidon::Hash::<_, S, ConstantLength<L>, T, RATE>::init().hash(message)
+        );
+    }
+
+    #[test]
+    fn test_constant_length() {
+        assert_constant_length::<Fp, P128Pow5T3, 3, 2, 1>();
+        assert_constant_length::<Fp, P128Pow5T3, 3, 2, 2>();
+        assert_constant_length::<Fp, P128Pow5T3, 3, 2, 3>();
+        assert_constant_length::<Fp, P128Pow5T3, 3, 2, 6>();
+        assert_constant_length::<Fq, P128Pow5T3, 3, 2, 5>();
+        assert_constant_length::<Fp, P128Pow5T5, 5, 4, 4>();
+        assert_constant_length::<Fq, P128Pow5T5, 5, 4, 7>();
+    }
+
+    // Messages that only differ by trailing zeros have different lengths, and so different digests
+    #[test]
+    fn test_trailing_zeros() {
+        let message = [Fp::random(OsRng), Fp::ZERO, Fp::ZERO];
+        let digests = (1..=3)
+            .map(|n| hash_variable_length::<Fp, P128Pow5T3, 3, 2>(&message[..n]))
+            .collect::<Vec<_>>();
+        assert_ne!(digests[0], digests[1]);
+        assert_ne!(digests[1], digests[2]);
+        assert_ne!(digests[0], digests[2]);
+    }
+
+    // Squeezing several outputs continues the sponge of the hash, and absorbing again changes the next outputs
+    #[test]
+    fn test_sponge() {
+        let message = [Fp::random(OsRng), Fp::random(OsRng)];
+        let domain = Fp::from_u128(2 << 64);
+        let mut sponge = Sponge::<Fp, P128Pow5T3, 3, 2>::new(domain);
+        sponge.absorb(message[0]);
+        sponge.absorb(message[1]);
+        let outputs = [sponge.squeeze(), sponge.squeeze(), sponge.squeeze()];
+        assert_eq!(
+            outputs[0],
+            hash_variable_length::<Fp, P128Pow5T3, 3, 2>(&message)
+        );
+
+        let mut state = [message[0], message[1], domain];
+        permute::<Fp, P128Pow5T3, 3, 2>(&mut state);
+        assert_eq!(outputs[1], state[1]);
+        permute::<Fp, P128Pow5T3, 3, 2>(&mut state);
+        assert_eq!(outputs[2], state[0]);
+
+        let mut other = Sponge::<Fp, P128Pow5T3, 3, 2>::new(domain);
+        other.absorb(message[0]);
+        other.absorb(message[1]);
+        other.squeeze();
+        other.absorb(message[0]);
+        assert_ne!(other.squeeze(), outputs[1]);
+
+        // Another domain gives another output
+        let mut other = Sponge::<Fp, P128Pow5T3, 3, 2>::new(domain + Fp::ONE);
+        other.absorb(message[0]);
+        other.absorb(message[1]);
+        assert_ne!(other.squeeze(), outputs[0]);
+    }
+}